@@ -1,11 +1,110 @@
+use crate::errors::TaskManagerError;
 use ic_cdk::export::candid::{CandidType};
 use serde::{Deserialize, Serialize};
 
+// Bounds enforced on every completion request before it reaches a backend.
+pub const MAX_NEW_TOKENS_LIMIT: u32 = 512;
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+
+// Why generation ended.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum FinishReason {
+    Length,       // `max_new_tokens` was reached
+    StopSequence, // One of the request's stop sequences was generated
+    EndOfText,    // The model emitted its end-of-text token
+}
 
 // Define a struct representing a text completion.
 #[derive(Clone, Deserialize, Serialize, CandidType)]
 pub struct Completion {
+    pub id: String,       // Key for looking the completion up again with `get_completion`
+    pub model_id: String, // Model that generated the text
+    pub user_id: String,  // User the completion was generated for
     pub prompt: String, // The input prompt that was provided to generate the completion
     pub generated_text: String, // The generated text based on the input prompt
-                        // TODO: Consider adding additional fields, such as a timestamp or confidence score.
-}
\ No newline at end of file
+    pub prompt_tokens: u32, // Number of tokens the prompt was encoded into
+    pub completion_tokens: u32, // Number of tokens generated
+    pub token_logprobs: Option<Vec<f32>>, // Log-probability of each generated token, if requested
+    pub finish_reason: FinishReason,
+    pub created_at: u64, // Nanoseconds since the epoch
+}
+
+// Parameters of a single completion. Zero `temperature` decodes greedily, and zero `top_k` or a
+// `top_p` of one disables that filter. Fields left out of a JSON request take their defaults.
+#[derive(Clone, Debug, Deserialize, Serialize, CandidType)]
+#[serde(default)]
+pub struct CompletionRequest {
+    pub prompt: String,
+    pub model_id: Option<String>, // Active model to use; None picks the default one
+    pub max_new_tokens: u32,      // Upper bound on generated tokens
+    pub temperature: f32,
+    pub top_k: u32,
+    pub top_p: f32,
+    pub repetition_penalty: f32, // Values above one discourage repeating earlier tokens
+    pub stop_sequences: Vec<String>, // Generation ends before the first occurrence of any of these
+    pub seed: Option<u64>,           // Makes sampling reproducible
+    pub logprobs: bool,              // Report the log-probability of every generated token
+}
+
+impl Default for CompletionRequest {
+    fn default() -> Self {
+        Self {
+            prompt: String::new(),
+            model_id: None,
+            max_new_tokens: 50,
+            temperature: 1.0,
+            top_k: 0,
+            top_p: 1.0,
+            repetition_penalty: 1.0,
+            stop_sequences: Vec::new(),
+            seed: None,
+            logprobs: false,
+        }
+    }
+}
+
+impl CompletionRequest {
+    pub fn validate(&self) -> Result<(), TaskManagerError> {
+        if self.prompt.is_empty() {
+            return Err(TaskManagerError::invalid_argument("prompt", "cannot be empty"));
+        }
+        if self.max_new_tokens == 0 || self.max_new_tokens > MAX_NEW_TOKENS_LIMIT {
+            return Err(TaskManagerError::invalid_argument(
+                "max_new_tokens",
+                &format!("must be between 1 and {}", MAX_NEW_TOKENS_LIMIT),
+            ));
+        }
+        if !self.temperature.is_finite() || self.temperature < 0.0 {
+            return Err(TaskManagerError::invalid_argument(
+                "temperature",
+                "must be a finite, non-negative number",
+            ));
+        }
+        if !(self.top_p > 0.0 && self.top_p <= 1.0) {
+            return Err(TaskManagerError::invalid_argument(
+                "top_p",
+                "must be greater than 0 and at most 1",
+            ));
+        }
+        if !self.repetition_penalty.is_finite() || self.repetition_penalty <= 0.0 {
+            return Err(TaskManagerError::invalid_argument(
+                "repetition_penalty",
+                "must be a finite, positive number",
+            ));
+        }
+        if self.stop_sequences.len() > MAX_STOP_SEQUENCES {
+            return Err(TaskManagerError::invalid_argument(
+                "stop_sequences",
+                &format!("at most {} are allowed", MAX_STOP_SEQUENCES),
+            ));
+        }
+        if self.stop_sequences.iter().any(String::is_empty) {
+            return Err(TaskManagerError::invalid_argument(
+                "stop_sequences",
+                "cannot contain an empty sequence",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use crate::completion::{CompletionRequest, FinishReason};
+use ic_cdk::export::candid::{CandidType};
+use serde::{Deserialize, Serialize};
+
+// How long a worker may go without posting before its job can be claimed by another worker.
+pub const COMPLETION_JOB_LEASE_DURATION_NS: u64 = 2 * 60 * 1_000_000_000;
+
+// Unfinished jobs fail once they are this old, so jobs no worker can serve leave the queue.
+pub const COMPLETION_JOB_EXPIRY_NS: u64 = 60 * 60 * 1_000_000_000;
+
+// Unfinished jobs the queue holds at once; further submissions are refused until some finish.
+pub const MAX_PENDING_COMPLETION_JOBS: usize = 1_000;
+
+// Bytes of output a job may hold per generated token; a token's text is rarely over 16 bytes.
+pub const MAX_COMPLETION_BYTES_PER_TOKEN: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum CompletionJobStatus {
+    Queued,    // Waiting for a worker holding a shard of the model
+    Running,   // Claimed by `worker_id`, which is streaming partial output
+    Completed, // Finished; `completion_id` refers to the stored completion
+    Failed,    // The worker gave up; `error` says why
+}
+
+// A completion request served asynchronously by a volunteer worker instead of the canister.
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct CompletionJob {
+    pub id: String,
+    pub model_id: String, // Resolved at submission, so workers know which shards are needed
+    pub user_id: String,  // User that submitted the job
+    pub request: CompletionRequest,
+    pub status: CompletionJobStatus,
+    pub worker_id: Option<String>, // User currently computing the job
+    pub lease_expires_at: Option<u64>,
+    pub output: String, // Text generated so far
+    pub completion_tokens: u32,
+    pub token_logprobs: Vec<f32>, // Collected only when the request asks for log-probabilities
+    pub completion_id: Option<String>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub verified: Option<bool>, // Admin verdict; the worker is only paid once accepted
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+// Progress posted by the worker holding a job. Text and log-probabilities are appended to what
+// was posted before; setting `finish_reason` or `error` ends the job.
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct CompletionJobUpdate {
+    pub text: String,
+    pub tokens: u32, // Tokens generated since the previous update
+    pub token_logprobs: Vec<f32>,
+    pub prompt_tokens: u32, // Only read from the final update
+    pub finish_reason: Option<FinishReason>,
+    pub error: Option<String>,
+}
+
+impl CompletionJob {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self.status,
+            CompletionJobStatus::Completed | CompletionJobStatus::Failed
+        )
+    }
+
+    // Queued jobs and running jobs whose worker went silent are both up for grabs.
+    pub fn is_claimable(&self, now: u64) -> bool {
+        match self.status {
+            CompletionJobStatus::Queued => true,
+            CompletionJobStatus::Running => !matches!(self.lease_expires_at, Some(at) if at > now),
+            _ => false,
+        }
+    }
+}
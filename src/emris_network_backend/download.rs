@@ -0,0 +1,56 @@
+use crate::errors::TaskManagerError;
+use ic_cdk::export::candid::{CandidType};
+use serde::{Deserialize, Serialize};
+
+// Largest slice returned by one read, keeping replies below the 2 MiB message limit.
+pub const MAX_DOWNLOAD_LENGTH: u64 = 1_900_000;
+
+// A blob held by the canister that can be read in slices.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum DownloadSource {
+    ModelChunk { chunk_id: String },
+    ModelWeights { model_id: String }, // Weights of the promoted version
+    ModelVersionWeights { model_id: String, version: String },
+    TrainingResults { task_id: String }, // Weights or delta submitted for a training task
+}
+
+// One slice of a blob. Clients check `slice_hash` per slice and `content_hash` once the whole
+// blob has been assembled.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub struct DownloadSlice {
+    pub data: Vec<u8>,
+    pub offset: u64,
+    pub total_size: u64,
+    pub slice_hash: Vec<u8>,   // SHA-256 of `data`
+    pub content_hash: Vec<u8>, // SHA-256 of the complete blob
+}
+
+impl DownloadSource {
+    // Sources served to anyone, including browsers through `http_request`. Model weights are
+    // already handed to every volunteer holding a shard.
+    pub fn is_public(&self) -> bool {
+        !matches!(self, DownloadSource::TrainingResults { .. })
+    }
+}
+
+// Check an `offset`/`length` read against a blob of `total_size` bytes and return the byte range.
+pub fn slice_range(
+    total_size: u64,
+    offset: u64,
+    length: u64,
+) -> Result<std::ops::Range<usize>, TaskManagerError> {
+    if length == 0 || length > MAX_DOWNLOAD_LENGTH {
+        return Err(TaskManagerError::invalid_argument(
+            "length",
+            &format!("must be between 1 and {}", MAX_DOWNLOAD_LENGTH),
+        ));
+    }
+    if offset >= total_size {
+        return Err(TaskManagerError::invalid_argument(
+            "offset",
+            &format!("must be below the blob size of {}", total_size),
+        ));
+    }
+    let end = offset.saturating_add(length).min(total_size);
+    Ok(offset as usize..end as usize)
+}
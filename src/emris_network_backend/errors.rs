@@ -1,25 +1,289 @@
-#[derive(Debug)]
+use crate::training_task::TrainingTaskStatus;
+use ic_cdk::export::candid::{CandidType};
+use serde::{Deserialize, Serialize};
+
+// Roles managed by the canister's controllers.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum Role {
+    Controller,
+    Admin,
+}
+
+// Returned by every `TaskManagerInterface` method and canister endpoint. Payloads carry the ids
+// and amounts involved so clients can branch on the variant instead of parsing messages.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
 pub enum TaskManagerError {
-    UserAlreadyExists,
-    UserNotFound,
-    ModelAlreadyExists,
-    ModelNotFound,
-    TrainingTaskAlreadyExists,
-    TrainingTaskNotFound,
-    InsufficientResources,
-    UnauthorizedAccess,
+    UserAlreadyExists { user_id: String },
+    UserNotFound { user_id: String },
+    ModelNotFound { model_id: String },
+    ModelNotActive { model_id: String },
+    ModelWeightsNotFound { model_id: String },
+    ModelVersionAlreadyExists { model_id: String, version: String },
+    ModelVersionNotFound { model_id: String, version: String },
+    NoPreviousModelVersion { model_id: String },
+    ModelChunkNotFound { chunk_id: String },
+    ShardVerificationNotFound { shard_id: String },
+    CompletionNotFound { completion_id: String },
+    CompletionJobNotFound { job_id: String },
+    CompletionJobNotAssigned { job_id: String, user_id: String },
+    CompletionQueueFull,
+    TrainingTaskAlreadyExists { task_id: String },
+    TrainingTaskNotFound { task_id: String },
+    TrainingTaskNotAssigned { task_id: String, user_id: String },
+    TrainingRoundNotFound { round_id: String },
+    TrainingRoundClosed { round_id: String },
+    UploadNotFound { upload_id: String },
+    NotUploadOwner { upload_id: String },
+    UploadIncomplete { upload_id: String, received: u32, expected: u32 },
+    UploadIntegrityFailed { upload_id: String, part: Option<u32> },
+    TooManyOpenUploads { limit: usize },
+    UploadStorageFull,
+    InvalidTrainingTaskTransition {
+        task_id: String,
+        from: TrainingTaskStatus,
+        to: TrainingTaskStatus,
+    },
+    InsufficientResources { model_id: String, required: u64, available: u64 },
+    PledgeExceedsResources { user_id: String, pledged: u64, resources: u64 },
+    NotEnoughContributors { required: u32, available: u32 },
+    NoActiveModels,
+    AnonymousCaller,
+    InvalidApiKey,
+    UnauthorizedAccess { required_role: Role },
+    NotUserOwner { user_id: String },
+    NotRegisteredUser,
+    PrincipalAlreadyOwnsUser { user_id: String },
+    RoleAlreadyAssigned { role: Role },
+    RoleNotAssigned { role: Role },
+    LastController,
+    ChunkNotAssigned { chunk_id: String, user_id: String },
+    LeaseExpired { chunk_id: String },
+    ChunkAlreadySubmitted { chunk_id: String },
+    DuplicateSubmission { shard_id: String, user_id: String },
+    IncompleteModel { model_id: String, present: u32, expected: u32 },
+    MissingChunk { model_id: String, index: u32 },
+    ChunkIntegrityFailed { chunk_id: String },
+    RateLimited { retry_after_ns: u64 },
+    InferenceBackendNotLoaded { model_id: String },
+    InferenceFailed(String),
+    InvalidArgument { argument: String, reason: String },
     RwLockPoisoned,
     GpuComputationFailed(String),
+    RandomnessUnavailable(String),
     // Additional error variants can be added here.
 }
 
+impl TaskManagerError {
+    pub fn invalid_argument(argument: &str, reason: &str) -> Self {
+        TaskManagerError::InvalidArgument {
+            argument: argument.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
 impl std::fmt::Display for TaskManagerError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            TaskManagerError::UserAlreadyExists => write!(f, "User already exists."),
-            TaskManagerError::UserNotFound => write!(f, "User not found."),
-            // Implement display for other error variants.
-            _ => write!(f, "An error occurred."),
+            TaskManagerError::UserAlreadyExists { user_id } => {
+                write!(f, "User '{}' already exists.", user_id)
+            }
+            TaskManagerError::UserNotFound { user_id } => {
+                write!(f, "User '{}' not found.", user_id)
+            }
+            TaskManagerError::ModelNotFound { model_id } => {
+                write!(f, "Model '{}' not found.", model_id)
+            }
+            TaskManagerError::ModelNotActive { model_id } => {
+                write!(f, "Model '{}' is not active.", model_id)
+            }
+            TaskManagerError::ModelWeightsNotFound { model_id } => {
+                write!(f, "No weights uploaded for model '{}'.", model_id)
+            }
+            TaskManagerError::ModelVersionAlreadyExists { model_id, version } => {
+                write!(f, "Model '{}' already has version {}.", model_id, version)
+            }
+            TaskManagerError::ModelVersionNotFound { model_id, version } => {
+                write!(f, "Model '{}' has no version {}.", model_id, version)
+            }
+            TaskManagerError::NoPreviousModelVersion { model_id } => write!(
+                f,
+                "Model '{}' has no earlier version with weights to roll back to.",
+                model_id
+            ),
+            TaskManagerError::ModelChunkNotFound { chunk_id } => {
+                write!(f, "Model chunk '{}' not found.", chunk_id)
+            }
+            TaskManagerError::ShardVerificationNotFound { shard_id } => {
+                write!(f, "No verification record for shard '{}'.", shard_id)
+            }
+            TaskManagerError::CompletionNotFound { completion_id } => {
+                write!(f, "Completion '{}' not found.", completion_id)
+            }
+            TaskManagerError::CompletionJobNotFound { job_id } => {
+                write!(f, "Completion job '{}' not found.", job_id)
+            }
+            TaskManagerError::CompletionJobNotAssigned { job_id, user_id } => write!(
+                f,
+                "Completion job '{}' is not assigned to user '{}'.",
+                job_id, user_id
+            ),
+            TaskManagerError::CompletionQueueFull => {
+                write!(f, "Too many completion jobs are waiting; try again later.")
+            }
+            TaskManagerError::TrainingTaskAlreadyExists { task_id } => {
+                write!(f, "Training task '{}' already exists.", task_id)
+            }
+            TaskManagerError::TrainingTaskNotFound { task_id } => {
+                write!(f, "Training task '{}' not found.", task_id)
+            }
+            TaskManagerError::TrainingTaskNotAssigned { task_id, user_id } => write!(
+                f,
+                "Training task '{}' is not assigned to user '{}'.",
+                task_id, user_id
+            ),
+            TaskManagerError::TrainingRoundNotFound { round_id } => {
+                write!(f, "Training round '{}' not found.", round_id)
+            }
+            TaskManagerError::TrainingRoundClosed { round_id } => {
+                write!(f, "Training round '{}' is no longer collecting.", round_id)
+            }
+            TaskManagerError::UploadNotFound { upload_id } => {
+                write!(f, "Upload '{}' not found.", upload_id)
+            }
+            TaskManagerError::NotUploadOwner { upload_id } => {
+                write!(f, "Upload '{}' was begun by another principal.", upload_id)
+            }
+            TaskManagerError::UploadIncomplete {
+                upload_id,
+                received,
+                expected,
+            } => write!(
+                f,
+                "Upload '{}' has {} of {} parts.",
+                upload_id, received, expected
+            ),
+            TaskManagerError::UploadIntegrityFailed { upload_id, part } => match part {
+                Some(part) => write!(
+                    f,
+                    "Part {} of upload '{}' does not match its hash.",
+                    part, upload_id
+                ),
+                None => write!(f, "Upload '{}' does not match its content hash.", upload_id),
+            },
+            TaskManagerError::TooManyOpenUploads { limit } => write!(
+                f,
+                "At most {} uploads can be open at once; commit or abort one first.",
+                limit
+            ),
+            TaskManagerError::UploadStorageFull => {
+                write!(f, "Too many bytes are being uploaded; try again later.")
+            }
+            TaskManagerError::InvalidTrainingTaskTransition { task_id, from, to } => write!(
+                f,
+                "Training task '{}' cannot move from {:?} to {:?}.",
+                task_id, from, to
+            ),
+            TaskManagerError::InsufficientResources {
+                model_id,
+                required,
+                available,
+            } => write!(
+                f,
+                "Insufficient resources for model '{}': {} required, {} available.",
+                model_id, required, available
+            ),
+            TaskManagerError::PledgeExceedsResources {
+                user_id,
+                pledged,
+                resources,
+            } => write!(
+                f,
+                "User '{}' would pledge {} resources but only has {}.",
+                user_id, pledged, resources
+            ),
+            TaskManagerError::NotEnoughContributors {
+                required,
+                available,
+            } => write!(
+                f,
+                "Not enough users with resources: {} required, {} available.",
+                required, available
+            ),
+            TaskManagerError::NoActiveModels => write!(f, "No active models available."),
+            TaskManagerError::InvalidApiKey => write!(f, "The API key is not valid."),
+            TaskManagerError::AnonymousCaller => write!(f, "Anonymous callers are not allowed."),
+            TaskManagerError::UnauthorizedAccess { required_role } => {
+                write!(f, "Unauthorized: caller lacks the {:?} role.", required_role)
+            }
+            TaskManagerError::NotUserOwner { user_id } => {
+                write!(f, "Unauthorized: caller does not own user '{}'.", user_id)
+            }
+            TaskManagerError::NotRegisteredUser => {
+                write!(f, "Unauthorized: caller is not a registered user.")
+            }
+            TaskManagerError::PrincipalAlreadyOwnsUser { user_id } => {
+                write!(f, "Principal already owns user '{}'.", user_id)
+            }
+            TaskManagerError::RoleAlreadyAssigned { role } => {
+                write!(f, "Principal already has the {:?} role.", role)
+            }
+            TaskManagerError::RoleNotAssigned { role } => {
+                write!(f, "Principal does not have the {:?} role.", role)
+            }
+            TaskManagerError::LastController => write!(f, "Cannot remove the last controller."),
+            TaskManagerError::ChunkNotAssigned { chunk_id, user_id } => write!(
+                f,
+                "Model chunk '{}' is not assigned to user '{}'.",
+                chunk_id, user_id
+            ),
+            TaskManagerError::LeaseExpired { chunk_id } => {
+                write!(f, "Lease on model chunk '{}' has expired.", chunk_id)
+            }
+            TaskManagerError::ChunkAlreadySubmitted { chunk_id } => {
+                write!(f, "Model chunk '{}' has already been submitted.", chunk_id)
+            }
+            TaskManagerError::DuplicateSubmission { shard_id, user_id } => write!(
+                f,
+                "User '{}' has already submitted results for shard '{}'.",
+                user_id, shard_id
+            ),
+            TaskManagerError::IncompleteModel {
+                model_id,
+                present,
+                expected,
+            } => write!(
+                f,
+                "Model '{}' is incomplete: {} of {} chunks present.",
+                model_id, present, expected
+            ),
+            TaskManagerError::MissingChunk { model_id, index } => {
+                write!(f, "Model '{}' is missing chunk {}.", model_id, index)
+            }
+            TaskManagerError::ChunkIntegrityFailed { chunk_id } => {
+                write!(f, "Chunk '{}' failed its integrity check.", chunk_id)
+            }
+            TaskManagerError::RateLimited { retry_after_ns } => write!(
+                f,
+                "Rate limit exceeded: retry in {} seconds.",
+                retry_after_ns.div_ceil(1_000_000_000)
+            ),
+            TaskManagerError::InferenceBackendNotLoaded { model_id } => {
+                write!(f, "No inference backend loaded for model '{}'.", model_id)
+            }
+            TaskManagerError::InferenceFailed(message) => {
+                write!(f, "Inference failed: {}", message)
+            }
+            TaskManagerError::InvalidArgument { argument, reason } => {
+                write!(f, "Invalid argument '{}': {}", argument, reason)
+            }
+            TaskManagerError::RwLockPoisoned => write!(f, "Internal error: RwLock is poisoned."),
+            TaskManagerError::GpuComputationFailed(message) => {
+                write!(f, "GPU computation failed: {}", message)
+            }
+            TaskManagerError::RandomnessUnavailable(message) => {
+                write!(f, "Could not obtain randomness: {}", message)
+            }
         }
     }
 }
@@ -1,12 +1,12 @@
-use rust_bert::gpt_neo::{GptNeoConfig, GptNeoForCausalLM, GptNeoGenerator};
-use rust_bert::pipelines::generation::{GenerateConfig, LanguageGenerator};
-use rust_bert::resources::{RemoteResource, Resource};
-use rust_bert::Config;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
-use tch::Device;
-use tch::nn::VarStore;
+use crate::completion::{CompletionRequest, FinishReason};
+use crate::errors::TaskManagerError;
+use crate::inference::{find_stop, Generation, TextGenerator};
+use rust_bert::gpt_neo::{
+    GptNeoConfigResources, GptNeoGenerator, GptNeoMergesResources, GptNeoModelResources,
+    GptNeoVocabResources,
+};
+use rust_bert::pipelines::generation_utils::{GenerateConfig, GenerateOptions, LanguageGenerator};
+use rust_bert::resources::RemoteResource;
 
 pub struct GptNeoTextGenerator {
     pub generator: GptNeoGenerator,
@@ -14,39 +14,95 @@ pub struct GptNeoTextGenerator {
 
 impl GptNeoTextGenerator {
     pub fn new() -> anyhow::Result<Self> {
-        let device = Device::cuda_if_available();
-        let config_resource = Resource::Remote(RemoteResource::from_pretrained(
-            GptNeoConfig::gpt_neo_125m(),
-        ));
-        let model_resource = Resource::Remote(RemoteResource::from_pretrained(
-            GptNeoForCausalLM::gpt_neo_125m(),
-        ));
-        let config_path = config_resource.get_local_path()?;
-        let weights_path = model_resource.get_local_path()?;
-        let config = GptNeoConfig::from_file(config_path);
-        let gpt_neo_model = GptNeoForCausalLM::from_file(VarStore::new(device), &weights_path, &config)?;
-        let generator = GptNeoGenerator::new(gpt_neo_model);
+        let generate_config = GenerateConfig {
+            model_resource: Box::new(RemoteResource::from_pretrained(
+                GptNeoModelResources::GPT_NEO_125M,
+            )),
+            config_resource: Box::new(RemoteResource::from_pretrained(
+                GptNeoConfigResources::GPT_NEO_125M,
+            )),
+            vocab_resource: Box::new(RemoteResource::from_pretrained(
+                GptNeoVocabResources::GPT_NEO_125M,
+            )),
+            merges_resource: Some(Box::new(RemoteResource::from_pretrained(
+                GptNeoMergesResources::GPT_NEO_125M,
+            ))),
+            ..Default::default()
+        };
+        let generator = GptNeoGenerator::new(generate_config)?;
 
         Ok(Self { generator })
     }
 
-    pub fn generate_text(&self, prompt: &str) -> String {
-        let generate_config = GenerateConfig {
-            max_length: 50,
-            do_sample: true,
+    pub fn generate_text(
+        &self,
+        request: &CompletionRequest,
+    ) -> Result<Generation, TaskManagerError> {
+        // rust-bert rejects a zero top-k, temperature or top-p, so greedy decoding and disabled
+        // filters are expressed by switching sampling off or leaving the option unset.
+        let do_sample = request.temperature > 0.0;
+        let generate_options = GenerateOptions {
+            max_new_tokens: Some(request.max_new_tokens as i64),
+            do_sample: Some(do_sample),
+            temperature: do_sample.then_some(request.temperature as f64),
+            top_k: (request.top_k > 0).then_some(request.top_k as i64),
+            top_p: Some(request.top_p as f64),
+            repetition_penalty: Some(request.repetition_penalty as f64),
+            output_scores: request.logprobs,
             ..Default::default()
         };
-        let output = self.generator.generate(Some(vec![prompt]), Some(generate_config));
-        output.get(0).unwrap().to_string()
-    }
+        if let Some(seed) = request.seed {
+            tch::manual_seed(seed as i64);
+        }
+        let output = self
+            .generator
+            .generate_indices(Some(&[request.prompt.as_str()]), Some(generate_options));
+        let (indices, token_scores) = match output.into_iter().next() {
+            Some(output) => (output.indices, output.token_scores),
+            None => {
+                return Err(TaskManagerError::InferenceFailed(
+                    "GPT-Neo produced no sequence".to_string(),
+                ))
+            }
+        };
 
-    // Save the GPT-Neo model to ONNX format
-    pub fn save_model_to_onnx(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
-        let input_shape = &[1, 128];
-        let onnx_bytes = self.generator.model.to_onnx(input_shape, true).unwrap();
-        let mut file = File::create(path)?;
-        file.write_all(&onnx_bytes)?;
-        Ok(())
+        // Causal generation echoes the prompt ids, so only the tail is the completion.
+        let tokenizer = self.generator.get_tokenizer();
+        let prompt_tokens = tokenizer.tokenize(&request.prompt).len().min(indices.len());
+        let completion = &indices[prompt_tokens..];
+        let mut text = tokenizer.decode(completion, true, true);
+        let mut completion_tokens = completion.len();
+        // Generation that ends early has emitted the end-of-text token.
+        let mut finish_reason = if completion_tokens < request.max_new_tokens as usize {
+            FinishReason::EndOfText
+        } else {
+            FinishReason::Length
+        };
+        // Stop sequences are not supported by rust-bert, so the text is cut afterwards.
+        if let Some(stop) = find_stop(&text, &request.stop_sequences) {
+            text.truncate(stop);
+            completion_tokens = tokenizer.tokenize(&text).len();
+            finish_reason = FinishReason::StopSequence;
+        }
+        let token_logprobs = token_scores.map(|scores| {
+            scores
+                .into_iter()
+                .take(completion_tokens)
+                .map(|score| score as f32)
+                .collect()
+        });
+        Ok(Generation {
+            text,
+            prompt_tokens: prompt_tokens as u32,
+            completion_tokens: completion_tokens as u32,
+            token_logprobs,
+            finish_reason,
+        })
     }
 }
 
+impl TextGenerator for GptNeoTextGenerator {
+    fn generate(&self, request: &CompletionRequest) -> Result<Generation, TaskManagerError> {
+        self.generate_text(request)
+    }
+}
@@ -0,0 +1,430 @@
+use crate::completion::CompletionRequest;
+use crate::download::{DownloadSlice, DownloadSource};
+use crate::errors::TaskManagerError;
+use crate::model::{ModelQuery, ModelSortKey};
+use crate::task_manager::TaskManagerInterface;
+use crate::training_task::TrainingTaskSummary;
+use ic_cdk::export::candid::{CandidType, Func};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+
+// Bytes sent per HTTP response or streaming callback; the gateway caps bodies near 2 MiB.
+pub const HTTP_STREAMING_CHUNK_SIZE: u64 = 1_000_000;
+
+// Page size of JSON model listings when the request does not give a `limit`.
+const DEFAULT_API_PAGE_SIZE: u32 = 20;
+
+// Request and response types of the HTTP gateway interface, so browsers and tooling outside the
+// IC can talk to the canister over plain HTTP.
+#[derive(Clone, Debug, Deserialize, CandidType)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, CandidType)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub streaming_strategy: Option<StreamingStrategy>,
+    pub upgrade: Option<bool>, // Asks the gateway to repeat the request as `http_request_update`
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType)]
+pub struct StreamingCallbackToken {
+    pub source: DownloadSource,
+    pub offset: u64, // Where the next body chunk starts
+}
+
+#[derive(Clone, Debug, CandidType)]
+pub enum StreamingStrategy {
+    Callback {
+        callback: Func,
+        token: StreamingCallbackToken,
+    },
+}
+
+#[derive(Clone, Debug, CandidType)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: Vec<u8>,
+    pub token: Option<StreamingCallbackToken>, // `None` once the body is complete
+}
+
+impl HttpResponse {
+    pub fn text(status_code: u16, body: &str) -> Self {
+        Self {
+            status_code,
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: body.as_bytes().to_vec(),
+            streaming_strategy: None,
+            upgrade: None,
+        }
+    }
+
+    pub fn json<T: Serialize>(status_code: u16, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self {
+                status_code,
+                headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+                body,
+                streaming_strategy: None,
+                upgrade: None,
+            },
+            Err(error) => Self::text(500, &error.to_string()),
+        }
+    }
+
+    // The error as JSON: the variant with its payload under `error`, and a readable `message`.
+    pub fn error(error: &TaskManagerError) -> Self {
+        let mut response = Self::json(
+            error_status(error),
+            &json!({ "error": error, "message": error.to_string() }),
+        );
+        if let TaskManagerError::RateLimited { retry_after_ns } = error {
+            let seconds = retry_after_ns.div_ceil(1_000_000_000);
+            response.headers.push(("Retry-After".to_string(), seconds.to_string()));
+        }
+        response
+    }
+
+    fn upgrade() -> Self {
+        Self {
+            upgrade: Some(true),
+            ..Self::text(200, "")
+        }
+    }
+}
+
+// The path of a request URL, without its query string.
+pub fn request_path(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or_default()
+}
+
+// Map the public download routes to their source:
+//   /chunks/{chunk_id}
+//   /models/{model_id}/weights
+//   /models/{model_id}/versions/{version}/weights
+pub fn download_route(path: &str) -> Option<DownloadSource> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["chunks", chunk_id] => Some(DownloadSource::ModelChunk {
+            chunk_id: chunk_id.to_string(),
+        }),
+        ["models", model_id, "weights"] => Some(DownloadSource::ModelWeights {
+            model_id: model_id.to_string(),
+        }),
+        ["models", model_id, "versions", version, "weights"] => {
+            Some(DownloadSource::ModelVersionWeights {
+                model_id: model_id.to_string(),
+                version: version.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+// Status code reported over HTTP for an error. Every variant is listed so new ones get a
+// deliberate mapping.
+pub fn error_status(error: &TaskManagerError) -> u16 {
+    use TaskManagerError::*;
+    match error {
+        InvalidArgument { .. }
+        | PledgeExceedsResources { .. }
+        | UploadIncomplete { .. }
+        | UploadIntegrityFailed { .. }
+        | ChunkIntegrityFailed { .. }
+        | IncompleteModel { .. }
+        | MissingChunk { .. } => 400,
+        AnonymousCaller | InvalidApiKey => 401,
+        UnauthorizedAccess { .. }
+        | NotUserOwner { .. }
+        | NotRegisteredUser
+        | NotUploadOwner { .. }
+        | ChunkNotAssigned { .. }
+        | CompletionJobNotAssigned { .. }
+        | TrainingTaskNotAssigned { .. } => 403,
+        UserNotFound { .. }
+        | ModelNotFound { .. }
+        | ModelWeightsNotFound { .. }
+        | ModelVersionNotFound { .. }
+        | NoPreviousModelVersion { .. }
+        | ModelChunkNotFound { .. }
+        | ShardVerificationNotFound { .. }
+        | CompletionNotFound { .. }
+        | CompletionJobNotFound { .. }
+        | TrainingTaskNotFound { .. }
+        | TrainingRoundNotFound { .. }
+        | UploadNotFound { .. } => 404,
+        UserAlreadyExists { .. }
+        | ModelVersionAlreadyExists { .. }
+        | TrainingTaskAlreadyExists { .. }
+        | PrincipalAlreadyOwnsUser { .. }
+        | RoleAlreadyAssigned { .. }
+        | RoleNotAssigned { .. }
+        | LastController
+        | ChunkAlreadySubmitted { .. }
+        | DuplicateSubmission { .. }
+        | LeaseExpired { .. }
+        | TrainingRoundClosed { .. }
+        | InvalidTrainingTaskTransition { .. } => 409,
+        RateLimited { .. } | TooManyOpenUploads { .. } => 429,
+        InferenceFailed(_) | RwLockPoisoned | GpuComputationFailed(_) => 500,
+        ModelNotActive { .. }
+        | NoActiveModels
+        | CompletionQueueFull
+        | UploadStorageFull
+        | InferenceBackendNotLoaded { .. }
+        | InsufficientResources { .. }
+        | NotEnoughContributors { .. }
+        | RandomnessUnavailable(_) => 503,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// The token for the body chunk following `slice`, if there is one.
+fn next_token(source: DownloadSource, slice: &DownloadSlice) -> Option<StreamingCallbackToken> {
+    let offset = slice.offset + slice.data.len() as u64;
+    (offset < slice.total_size).then_some(StreamingCallbackToken { source, offset })
+}
+
+// Answer a GET for a public download with its first body chunk, streaming the rest through
+// `callback`.
+pub fn serve_download(
+    task_manager: &impl TaskManagerInterface,
+    source: DownloadSource,
+    callback: Func,
+) -> HttpResponse {
+    let slice = match task_manager.read_download(&source, 0, HTTP_STREAMING_CHUNK_SIZE) {
+        Ok(slice) => slice,
+        Err(error) => return HttpResponse::error(&error),
+    };
+    let headers = vec![
+        ("Content-Type".to_string(), "application/octet-stream".to_string()),
+        ("Content-Length".to_string(), slice.total_size.to_string()),
+        ("X-Content-SHA256".to_string(), hex(&slice.content_hash)),
+    ];
+    let streaming_strategy = next_token(source, &slice)
+        .map(|token| StreamingStrategy::Callback { callback, token });
+    HttpResponse {
+        status_code: 200,
+        headers,
+        body: slice.data,
+        streaming_strategy,
+        upgrade: None,
+    }
+}
+
+// Body chunk for a streaming callback. The status line has already been sent, so a failed read
+// can only end the body early; clients detect that through the length and hash headers.
+pub fn stream_download(
+    task_manager: &impl TaskManagerInterface,
+    token: StreamingCallbackToken,
+) -> StreamingCallbackHttpResponse {
+    match task_manager.read_download(&token.source, token.offset, HTTP_STREAMING_CHUNK_SIZE) {
+        Ok(slice) => StreamingCallbackHttpResponse {
+            token: next_token(token.source, &slice),
+            body: slice.data,
+        },
+        Err(_) => StreamingCallbackHttpResponse {
+            body: Vec::new(),
+            token: None,
+        },
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Query string parameters of a request URL.
+fn query_params(url: &str) -> HashMap<String, String> {
+    let query = url.split_once('?').map_or("", |(_, query)| query);
+    let query = query.split('#').next().unwrap_or_default();
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn model_query(url: &str) -> Result<ModelQuery, TaskManagerError> {
+    let params = query_params(url);
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse()
+            .map_err(|_| TaskManagerError::invalid_argument("limit", "must be a number"))?,
+        None => DEFAULT_API_PAGE_SIZE,
+    };
+    let sort_by = match params.get("sort_by").map(String::as_str) {
+        None | Some("id") => ModelSortKey::Id,
+        Some("created_at") => ModelSortKey::CreatedAt,
+        Some("min_resources") => ModelSortKey::MinResources,
+        Some(_) => {
+            return Err(TaskManagerError::invalid_argument(
+                "sort_by",
+                "must be id, created_at or min_resources",
+            ))
+        }
+    };
+    Ok(ModelQuery {
+        cursor: params.get("cursor").cloned(),
+        limit,
+        sort_by,
+        descending: params.get("descending").map(String::as_str) == Some("true"),
+        ..Default::default()
+    })
+}
+
+// The user an `Authorization: Bearer <key>` header acts as. Gateway calls are anonymous, so API
+// keys are the only way to identify a user over HTTP.
+fn bearer_user(
+    task_manager: &impl TaskManagerInterface,
+    request: &HttpRequest,
+) -> Result<String, TaskManagerError> {
+    let key = request
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+        .and_then(|(_, value)| value.strip_prefix("Bearer "))
+        .ok_or(TaskManagerError::AnonymousCaller)?;
+    task_manager.find_api_key_user(key.trim())
+}
+
+fn check_owner(user_id: &str, owner: &str) -> Result<(), TaskManagerError> {
+    if user_id == owner {
+        Ok(())
+    } else {
+        Err(TaskManagerError::NotUserOwner {
+            user_id: owner.to_string(),
+        })
+    }
+}
+
+fn respond(result: Result<HttpResponse, TaskManagerError>) -> HttpResponse {
+    result.unwrap_or_else(|error| HttpResponse::error(&error))
+}
+
+// JSON REST routes served from queries. Requests that change state are upgraded to
+// `http_request_update`.
+//   GET /api/models[?limit=&cursor=&sort_by=&descending=]
+//   GET /api/models/needing-resources[?...]
+//   GET /api/models/{model_id}
+//   GET /api/rewards
+//   GET /api/completions/{completion_id}
+//   GET /api/completion-jobs/{job_id}
+//   GET /api/training-tasks/{task_id}
+pub fn handle_api_query(
+    task_manager: &impl TaskManagerInterface,
+    request: &HttpRequest,
+) -> HttpResponse {
+    if request.method == "POST" {
+        return HttpResponse::upgrade();
+    }
+    if request.method != "GET" {
+        return HttpResponse::text(405, "Method not allowed.");
+    }
+    let path = request_path(&request.url);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    respond(match segments.as_slice() {
+        ["api", "models"] => model_query(&request.url)
+            .and_then(|query| task_manager.get_active_models(&query))
+            .map(|page| HttpResponse::json(200, &page)),
+        ["api", "models", "needing-resources"] => model_query(&request.url)
+            .and_then(|query| task_manager.get_models_needing_resources(&query))
+            .map(|page| HttpResponse::json(200, &page)),
+        ["api", "models", model_id] => task_manager
+            .get_model(model_id)
+            .map(|model| HttpResponse::json(200, &model)),
+        ["api", "rewards"] => bearer_user(task_manager, request).and_then(|user_id| {
+            let rewards = task_manager.get_rewards(&user_id)?;
+            Ok(HttpResponse::json(200, &json!({ "user_id": user_id, "rewards": rewards })))
+        }),
+        ["api", "completions", completion_id] => {
+            bearer_user(task_manager, request).and_then(|user_id| {
+                let completion = task_manager.get_completion(completion_id)?;
+                check_owner(&user_id, &completion.user_id)?;
+                Ok(HttpResponse::json(200, &completion))
+            })
+        }
+        ["api", "completion-jobs", job_id] => {
+            bearer_user(task_manager, request).and_then(|user_id| {
+                let job = task_manager.get_completion_job(job_id)?;
+                check_owner(&user_id, &job.user_id)?;
+                Ok(HttpResponse::json(200, &job))
+            })
+        }
+        // Status only; the training data and weights can be large and are left out.
+        ["api", "training-tasks", task_id] => task_manager
+            .get_training_task(task_id)
+            .map(|task| HttpResponse::json(200, &TrainingTaskSummary::from(&task))),
+        _ => Ok(HttpResponse::text(404, "Not found.")),
+    })
+}
+
+// JSON REST routes that change state; each costs the key's user a rate-limit token.
+//   POST /api/completions       body: CompletionRequest
+//   POST /api/completion-jobs   body: CompletionRequest
+pub fn handle_api_update(
+    task_manager: &mut impl TaskManagerInterface,
+    request: &HttpRequest,
+    now: u64,
+) -> HttpResponse {
+    if request.method != "POST" {
+        return HttpResponse::text(405, "Method not allowed.");
+    }
+    let path = request_path(&request.url);
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let endpoint = match segments.as_slice() {
+        ["api", endpoint @ ("completions" | "completion-jobs")] => *endpoint,
+        _ => return HttpResponse::text(404, "Not found."),
+    };
+    respond((|| {
+        let mut completion_request: CompletionRequest = serde_json::from_slice(&request.body)
+            .map_err(|error| TaskManagerError::invalid_argument("body", &error.to_string()))?;
+        // Reject malformed requests before they cost the caller a token.
+        completion_request.validate()?;
+        let user_id = bearer_user(task_manager, request)?;
+        task_manager.consume_rate_limit_token(&user_id, now)?;
+        if endpoint == "completions" {
+            completion_request.seed.get_or_insert(now);
+            let completion = task_manager.generate_completion(&user_id, &completion_request, now)?;
+            Ok(HttpResponse::json(201, &completion))
+        } else {
+            let job_id = task_manager.submit_completion_job(&user_id, completion_request, now)?;
+            Ok(HttpResponse::json(202, &task_manager.get_completion_job(&job_id)?))
+        }
+    })())
+}
@@ -0,0 +1,160 @@
+use crate::completion::{CompletionRequest, FinishReason};
+use crate::errors::TaskManagerError;
+use crate::gpt_neo::GptNeoTextGenerator;
+use crate::onnx_generator::OnnxTextGenerator;
+use ic_cdk::export::candid::{CandidType};
+use serde::{Deserialize, Serialize};
+
+// Engine a model's completions are generated with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum InferenceBackend {
+    #[default]
+    GptNeo, // Pretrained GPT-Neo 125M through rust-bert; takes no uploaded weights
+    Onnx,   // ONNX graph uploaded as the model's weights, run through wonnx
+}
+
+// Text produced by a backend together with the token counts it was billed for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Generation {
+    pub text: String,           // Generated continuation, without the prompt
+    pub prompt_tokens: u32,     // Tokens the prompt was encoded into
+    pub completion_tokens: u32, // Tokens generated after the prompt
+    pub token_logprobs: Option<Vec<f32>>, // One per generated token when the request asks for them
+    pub finish_reason: FinishReason,
+}
+
+// A loaded model able to continue a prompt. Loaded backends are held in memory only, so they
+// must be loaded again after an upgrade.
+pub trait TextGenerator: Send {
+    fn generate(&self, request: &CompletionRequest) -> Result<Generation, TaskManagerError>;
+}
+
+// Byte offset of the earliest stop sequence in `text`, where the completion has to be cut.
+pub fn find_stop(text: &str, stop_sequences: &[String]) -> Option<usize> {
+    stop_sequences
+        .iter()
+        .filter_map(|stop| text.find(stop.as_str()))
+        .min()
+}
+
+// Natural log of the softmax probability the model assigned to `token`.
+pub fn log_probability(logits: &[f32], token: usize) -> f32 {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let sum: f32 = logits.iter().map(|logit| (logit - max).exp()).sum();
+    logits[token] - max - sum.ln()
+}
+
+// SplitMix64, so seeded sampling is reproducible without pulling in a `rand` dependency.
+pub struct SamplingRng(u64);
+
+impl SamplingRng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+// Choose the next token from one position's `logits`, applying the request's repetition
+// penalty, temperature, top-k and top-p in that order. `logits` must not be empty.
+pub fn sample_token(
+    logits: &[f32],
+    previous: &[i64],
+    request: &CompletionRequest,
+    rng: &mut SamplingRng,
+) -> usize {
+    let mut scores = logits.to_vec();
+    if request.repetition_penalty != 1.0 {
+        let mut seen = previous.to_vec();
+        seen.sort_unstable();
+        seen.dedup();
+        for id in seen {
+            if let Some(score) = scores.get_mut(id as usize) {
+                if *score > 0.0 {
+                    *score /= request.repetition_penalty;
+                } else {
+                    *score *= request.repetition_penalty;
+                }
+            }
+        }
+    }
+
+    // Highest score first; ties go to the lower id so greedy decoding is deterministic.
+    let mut candidates: Vec<(usize, f32)> = scores.into_iter().enumerate().collect();
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    if request.temperature == 0.0 {
+        return candidates[0].0;
+    }
+    if request.top_k > 0 {
+        candidates.truncate(request.top_k as usize);
+    }
+
+    let max = candidates[0].1 / request.temperature;
+    let mut probabilities: Vec<f32> = candidates
+        .iter()
+        .map(|&(_, score)| (score / request.temperature - max).exp())
+        .collect();
+    let total: f32 = probabilities.iter().sum();
+    probabilities.iter_mut().for_each(|p| *p /= total);
+    if request.top_p < 1.0 {
+        let mut cumulative = 0.0;
+        let keep = probabilities
+            .iter()
+            .position(|p| {
+                cumulative += p;
+                cumulative >= request.top_p
+            })
+            .map_or(probabilities.len(), |index| index + 1);
+        probabilities.truncate(keep);
+    }
+
+    let mut target = rng.next_f32() * probabilities.iter().sum::<f32>();
+    for (&(token, _), probability) in candidates.iter().zip(&probabilities) {
+        if target < *probability {
+            return token;
+        }
+        target -= probability;
+    }
+    candidates[probabilities.len() - 1].0
+}
+
+// Build the backend a model is configured for. GPT-Neo fetches its pretrained weights itself, as
+// rust-bert only loads weights from files; ONNX runs the graph uploaded as the model's weights
+// with the model's uploaded tokenizer.
+pub fn load_text_generator(
+    backend: InferenceBackend,
+    model_id: &str,
+    weights: Option<&[u8]>,
+    tokenizer: Option<&[u8]>,
+) -> Result<Box<dyn TextGenerator>, TaskManagerError> {
+    match backend {
+        InferenceBackend::GptNeo => {
+            let generator = GptNeoTextGenerator::new()
+                .map_err(|error| TaskManagerError::InferenceFailed(error.to_string()))?;
+            Ok(Box::new(generator))
+        }
+        InferenceBackend::Onnx => {
+            let weights = weights.ok_or_else(|| TaskManagerError::ModelWeightsNotFound {
+                model_id: model_id.to_string(),
+            })?;
+            let tokenizer = tokenizer.ok_or_else(|| {
+                TaskManagerError::InferenceFailed(format!(
+                    "model {} has no tokenizer; upload its tokenizer.json first",
+                    model_id
+                ))
+            })?;
+            Ok(Box::new(OnnxTextGenerator::from_bytes(weights, tokenizer)?))
+        }
+    }
+}
@@ -1,33 +1,193 @@
+use ic_cdk::export::candid::Func;
+use ic_cdk::export::Principal;
 use ic_cdk_macros::*;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use once_cell::sync::Lazy;
 
 mod completion;
+mod completion_job;
+mod download;
+mod errors;
+mod http;
+mod inference;
+mod model;
 mod model_chunk;
+mod rate_limit;
 mod task_manager;
 mod task_manager_impl;
+mod training_round;
 mod training_task;
+mod upload;
 mod user;
 mod fine_tuning;
+mod gpt_neo;
+mod onnx_generator;
+mod tokenizer;
+mod reputation;
+mod stable_state;
+mod verification;
 
 use completion::*;
+use completion_job::*;
+use download::*;
+use errors::*;
+use http::*;
+use model::*;
 use model_chunk::*;
+use rate_limit::*;
+use reputation::*;
 use task_manager::*;
 use task_manager_impl::*;
+use training_round::*;
 use training_task::*;
+use upload::*;
 use user::*;
+use verification::*;
 
 static TASK_MANAGER: Lazy<Arc<Mutex<TaskManagerImpl>>> = Lazy::new(|| {
   Arc::new(Mutex::new(TaskManagerImpl::default()))
 });
 
 // Error handling for RwLock poisoning
-fn handle_rwlock_poisoned<T>(_: std::sync::PoisonError<T>) -> String {
-    "Internal error: RwLock is poisoned.".to_string()
+fn handle_rwlock_poisoned<T>(_: std::sync::PoisonError<T>) -> TaskManagerError {
+    TaskManagerError::RwLockPoisoned
+}
+
+// How often the lease reclaimer sweeps for chunks whose holders stopped sending heartbeats, and
+// for uploads that stopped receiving parts.
+const LEASE_RECLAIM_INTERVAL: Duration = Duration::from_secs(60);
+
+// How often model activation is reconciled against the capacity contributors provide.
+const MODEL_RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+// Timers do not survive upgrades, so this runs from both `init` and `post_upgrade`.
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(LEASE_RECLAIM_INTERVAL, || {
+        if let Ok(mut task_manager) = TASK_MANAGER.lock() {
+            let now = ic_cdk::api::time();
+            task_manager.reclaim_expired_leases(now);
+            task_manager.drop_expired_uploads(now);
+        }
+    });
+    ic_cdk_timers::set_timer_interval(MODEL_RECONCILE_INTERVAL, || {
+        if let Ok(mut task_manager) = TASK_MANAGER.lock() {
+            for event in task_manager.reconcile_model_capacity(ic_cdk::api::time()) {
+                ic_cdk::println!(
+                    "Model '{}' {:?}: capacity {} of {} required.",
+                    event.model_id,
+                    event.kind,
+                    event.capacity,
+                    event.min_resources
+                );
+            }
+        }
+    });
+}
+
+// Every endpoint authorizes against the caller's principal, so anonymous calls are rejected
+// up front.
+fn authenticated_caller() -> Result<Principal, TaskManagerError> {
+    let caller = ic_cdk::caller();
+    if caller == Principal::anonymous() {
+        return Err(TaskManagerError::AnonymousCaller);
+    }
+    Ok(caller)
+}
+
+// Charge one token from the caller's rate-limit bucket before serving a user-facing call and
+// return the caller's user id.
+fn consume_caller_token(
+    task_manager: &mut TaskManagerImpl,
+    caller: &Principal,
+) -> Result<String, TaskManagerError> {
+    let user_id = task_manager.find_user_id(caller)?;
+    task_manager.consume_rate_limit_token(&user_id, ic_cdk::api::time())?;
+    Ok(user_id)
+}
+
+#[init]
+fn init() {
+    TASK_MANAGER
+        .lock()
+        .expect("Task manager lock poisoned during init.")
+        .init(ic_cdk::caller());
+    start_timers();
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let task_manager = TASK_MANAGER
+        .lock()
+        .expect("Task manager lock poisoned during pre_upgrade.");
+    stable_state::save_state(&task_manager).unwrap_or_else(|e| ic_cdk::trap(&e));
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let mut restored = stable_state::restore_state().unwrap_or_else(|e| ic_cdk::trap(&e));
+    // State saved before roles existed has no controllers; hand control to the upgrader.
+    if restored.get_controllers().is_empty() {
+        restored.init(ic_cdk::caller());
+    }
+    *TASK_MANAGER
+        .lock()
+        .expect("Task manager lock poisoned during post_upgrade.") = restored;
+    start_timers();
+}
+
+#[update]
+fn add_controller(principal: Principal) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_controller_access(&caller)?;
+    task_manager.add_controller(principal)
+}
+
+#[update]
+fn remove_controller(principal: Principal) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_controller_access(&caller)?;
+    task_manager.remove_controller(&principal)
+}
+
+#[update]
+fn add_admin(principal: Principal) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_controller_access(&caller)?;
+    task_manager.add_admin(principal)
+}
+
+#[update]
+fn remove_admin(principal: Principal) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_controller_access(&caller)?;
+    task_manager.remove_admin(&principal)
+}
+
+#[query]
+fn get_controllers() -> Result<Vec<Principal>, TaskManagerError> {
+    Ok(TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_controllers())
+}
+
+#[query]
+fn get_admins() -> Result<Vec<Principal>, TaskManagerError> {
+    Ok(TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_admins())
 }
 
 #[update]
-fn register_user(user: User) -> Result<String, String> {
+fn register_user(mut user: User) -> Result<String, TaskManagerError> {
+    user.owner = Some(authenticated_caller()?);
+    user.tier = RateLimitTier::Free;
     TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
@@ -35,106 +195,688 @@ fn register_user(user: User) -> Result<String, String> {
 }
 
 #[update]
-fn update_user_resources(id: String, resources: u64) -> Result<(), String> {
+fn update_user_resources(id: String, resources: u64) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &id)?;
+    consume_caller_token(&mut task_manager, &caller)?;
+    task_manager.update_user_resources(&id, resources)
+}
+
+// Commit part of a user's resources to one model; an amount of zero withdraws the pledge.
+#[update]
+fn pledge_resources(
+    user_id: String,
+    model_id: String,
+    amount: u64,
+) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    consume_caller_token(&mut task_manager, &caller)?;
+    task_manager.pledge_resources(&user_id, &model_id, amount)
+}
+
+#[query]
+fn get_user_pledges(user_id: String) -> Result<Vec<ResourcePledge>, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.get_user_pledges(&user_id)
+}
+
+#[query]
+fn get_model_capacity(model_id: String) -> Result<u64, TaskManagerError> {
     TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
-        .update_user_resources(&id, resources)
+        .get_model_capacity(&model_id)
 }
 
 #[update]
-fn distribute_model_chunks() -> Result<(), String> {
+fn set_user_tier(user_id: String, tier: RateLimitTier) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.set_user_tier(&user_id, tier)
+}
+
+#[update]
+fn set_rate_limit_config(config: RateLimitConfig) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.set_rate_limit_config(config)
+}
+
+#[query]
+fn get_rate_limit_config() -> Result<RateLimitConfig, TaskManagerError> {
+    Ok(TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_rate_limit_config())
+}
+
+#[update]
+fn upload_model_weights(model_id: String, weights: Vec<u8>) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.upload_model_weights(&model_id, weights)
+}
+
+// Store the `tokenizer.json` the ONNX backend encodes prompts and decodes completions with.
+#[update]
+fn upload_model_tokenizer(model_id: String, tokenizer: Vec<u8>) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.upload_model_tokenizer(&model_id, tokenizer)
+}
+
+#[update]
+fn distribute_model_chunks(model_id: String, chunk_size: u64) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.distribute_model_chunks(&model_id, chunk_size, ic_cdk::api::time())
+}
+
+#[update]
+fn submit_computed_chunk(
+    chunk: ModelChunk,
+    computed_results: Vec<u32>,
+) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &chunk.user_id)?;
+    task_manager.submit_computed_chunk(chunk, computed_results, ic_cdk::api::time())
+}
+
+#[query]
+fn get_reputation(user_id: String) -> Result<Reputation, TaskManagerError> {
+    authenticated_caller()?;
     TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
-        .distribute_model_chunks()
+        .get_reputation(&user_id)
+}
+
+// Only admins see the results of a shard that is still pending.
+#[query]
+fn get_shard_verification(shard_id: String) -> Result<ShardVerification, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    let verification = task_manager.get_shard_verification(&shard_id)?;
+    if task_manager.check_admin_access(&caller).is_ok() {
+        Ok(verification)
+    } else {
+        Ok(verification.redacted())
+    }
 }
 
 #[update]
-fn submit_computed_chunk(chunk: ModelChunk, computed_results: Vec<u32>) -> Result<(), String> {
-  TASK_MANAGER
-      .lock() // Use lock() to acquire a MutexGuard
-      .map_err(handle_rwlock_poisoned)?
-      .submit_computed_chunk(chunk, computed_results)
+fn set_verification_config(config: VerificationConfig) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.set_verification_config(config)
 }
 
 #[query]
-fn get_model_chunks(user_id: String) -> Result<Vec<ModelChunk>, String> {
+fn get_verification_config() -> Result<VerificationConfig, TaskManagerError> {
+    Ok(TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_verification_config())
+}
+
+// Heartbeat from a worker still processing a chunk; returns the new lease expiry.
+#[update]
+fn renew_chunk_lease(user_id: String, chunk_id: String) -> Result<u64, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.renew_chunk_lease(&user_id, &chunk_id, ic_cdk::api::time())
+}
+
+#[query]
+fn get_model_chunks(user_id: String) -> Result<Vec<ModelChunk>, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.get_model_chunks(&user_id)
+}
+
+#[query]
+fn get_reassembled_model(model_id: String) -> Result<Vec<u8>, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.get_reassembled_model(&model_id)
+}
+
+// Verify the model's chunks and make the reassembled blob its current weights.
+// Returns the SHA-256 of the reassembled weights.
+#[update]
+fn reassemble_model(model_id: String) -> Result<Vec<u8>, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.reassemble_model(&model_id)
+}
+
+#[query]
+fn get_rewards(user_id: String) -> Result<u64, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.get_rewards(&user_id)
+}
+
+// Create a key for the JSON API under `/api/`. It is only returned here; the canister keeps a
+// hash of it.
+#[update]
+async fn create_api_key(user_id: String) -> Result<String, TaskManagerError> {
+    let caller = authenticated_caller()?;
     TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
-        .get_model_chunks(&user_id)
+        .check_user_access(&caller, &user_id)?;
+    let (random,) = ic_cdk::api::management_canister::main::raw_rand()
+        .await
+        .map_err(|(_, message)| TaskManagerError::RandomnessUnavailable(message))?;
+    let key: String = random.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let key = format!("emris-{}", key);
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.add_api_key(&user_id, &key)?;
+    Ok(key)
+}
+
+#[update]
+fn revoke_api_keys(user_id: String) -> Result<u32, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.revoke_api_keys(&user_id)
+}
+
+#[update]
+fn register_model(mut model: Model) -> Result<String, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    model.owner = Some(caller);
+    task_manager.register_model(model, ic_cdk::api::time())
+}
+
+// Store weights for a published version ahead of promoting it.
+#[update]
+fn upload_model_version_weights(
+    model_id: String,
+    version: String,
+    weights: Vec<u8>,
+) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.upload_model_version_weights(&model_id, &version, weights)
 }
 
 #[query]
-fn get_rewards(user_id: String) -> Result<u64, String> {
+fn get_model_versions(model_id: String) -> Result<Vec<ModelVersion>, TaskManagerError> {
     TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
-        .get_rewards(&user_id)
+        .get_model_versions(&model_id)
 }
 
 #[update]
-fn register_model(admin_token: String, model: Model) -> Result<String, String> {
+fn promote_model_version(model_id: String, version: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
     let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
-    // task_manager.check_admin_access(&admin_token)?;
-    task_manager.register_model(model)
+    task_manager.check_admin_access(&caller)?;
+    task_manager.promote_model_version(&model_id, &version, ic_cdk::api::time())
+}
+
+// Promote the highest version below the current one that has weights; returns that version.
+#[update]
+fn rollback_model(model_id: String) -> Result<String, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.rollback_model(&model_id, ic_cdk::api::time())
+}
+
+#[query]
+fn get_model(model_id: String) -> Result<Model, TaskManagerError> {
+    TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_model(&model_id)
+}
+
+#[query]
+fn get_models_by_architecture(
+    architecture: ModelArchitecture,
+    query: ModelQuery,
+) -> Result<ModelPage, TaskManagerError> {
+    TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_models_by_architecture(architecture, &query)
 }
 
 #[update]
-fn activate_model(admin_token: String, model_id: String) -> Result<(), String> {
+fn activate_model(model_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
     let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
-    // task_manager.check_admin_access(&admin_token)?;
+    task_manager.check_admin_access(&caller)?;
     task_manager.activate_model(&model_id)
 }
 
 #[update]
-fn deactivate_model(admin_token: String, model_id: String) -> Result<(), String> {
+fn deactivate_model(model_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
     let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
-    // task_manager.check_admin_access(&admin_token)?;
+    task_manager.check_admin_access(&caller)?;
     task_manager.deactivate_model(&model_id)
 }
 
+// Load the inference engine selected by the model's `backend`. Loaded backends live on the heap
+// only, so this has to be repeated after every upgrade.
+#[update]
+fn load_inference_backend(model_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.load_inference_backend(&model_id)
+}
+
+#[update]
+fn set_model_capacity_policy(
+    model_id: String,
+    policy: CapacityPolicy,
+) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.set_model_capacity_policy(&model_id, policy)
+}
+
+// Transitions made by the capacity reconciler, oldest first, starting after sequence `after`.
 #[query]
-fn get_active_models(offset: usize, limit: usize) -> Result<Vec<Model>, String> {
+fn get_model_events(after: Option<u64>, limit: usize) -> Result<Vec<ModelEvent>, TaskManagerError> {
     Ok(TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
-        .get_active_models(offset, limit))
+        .get_model_events(after, limit))
 }
 
 #[query]
-fn get_models_needing_resources(offset: usize, limit: usize) -> Result<Vec<Model>, String> {
-    Ok(TASK_MANAGER
+fn get_active_models(query: ModelQuery) -> Result<ModelPage, TaskManagerError> {
+    TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
-        .get_models_needing_resources(offset, limit))
+        .get_active_models(&query)
+}
+
+#[query]
+fn get_models_needing_resources(query: ModelQuery) -> Result<ModelPage, TaskManagerError> {
+    TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_models_needing_resources(&query)
+}
+
+// An update rather than a query: token consumption has to be committed to be enforced.
+#[update]
+fn generate_completion(mut request: CompletionRequest) -> Result<Completion, TaskManagerError> {
+    // Reject malformed requests before they cost the caller a token.
+    request.validate()?;
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    let user_id = consume_caller_token(&mut task_manager, &caller)?;
+    let now = ic_cdk::api::time();
+    // Unseeded requests still sample differently from one call to the next.
+    request.seed.get_or_insert(now);
+    task_manager.generate_completion(&user_id, &request, now)
+}
+
+// Completions are visible to the user they were generated for and to admins.
+#[query]
+fn get_completion(completion_id: String) -> Result<Completion, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    let completion = task_manager.get_completion(&completion_id)?;
+    if task_manager.check_admin_access(&caller).is_err() {
+        task_manager.check_user_access(&caller, &completion.user_id)?;
+    }
+    Ok(completion)
+}
+
+// Queue a completion for the volunteer network instead of generating it in the canister.
+// Returns the job id to poll with `get_completion_job`.
+#[update]
+fn submit_completion_job(request: CompletionRequest) -> Result<String, TaskManagerError> {
+    request.validate()?;
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    let user_id = consume_caller_token(&mut task_manager, &caller)?;
+    task_manager.submit_completion_job(&user_id, request, ic_cdk::api::time())
 }
 
+// Hand the oldest waiting job for a model the worker holds shards of to `user_id`, if any.
+#[update]
+fn claim_completion_job(user_id: String) -> Result<Option<CompletionJob>, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.claim_completion_job(&user_id, ic_cdk::api::time())
+}
+
+#[update]
+fn post_completion_job_update(
+    user_id: String,
+    job_id: String,
+    update: CompletionJobUpdate,
+) -> Result<CompletionJobStatus, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.post_completion_job_update(&user_id, &job_id, update, ic_cdk::api::time())
+}
+
+// Accept or reject a completed job's output, paying or slashing the worker that produced it.
+#[update]
+fn review_completion_job(job_id: String, accepted: bool) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.review_completion_job(&job_id, accepted, ic_cdk::api::time())
+}
+
+// Jobs are visible to the user that submitted them and to admins.
 #[query]
-fn generate_completion(prompt: String) -> Result<Completion, String> {
-    // Input validation example
-    if prompt.is_empty() {
-        return Err("Prompt cannot be empty.".to_string());
+fn get_completion_job(job_id: String) -> Result<CompletionJob, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    let job = task_manager.get_completion_job(&job_id)?;
+    if task_manager.check_admin_access(&caller).is_err() {
+        task_manager.check_user_access(&caller, &job.user_id)?;
     }
+    Ok(job)
+}
 
+#[update]
+fn create_training_task(task: TrainingTask) -> Result<String, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.create_training_task(task, ic_cdk::api::time())
+}
+
+// The training data and submitted weights are left out; the assignee receives the data when
+// claiming the task, and results are fetched through `read_download`.
+#[query]
+fn get_training_task(task_id: String) -> Result<TrainingTaskSummary, TaskManagerError> {
+    let task = TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_training_task(&task_id)?;
+    Ok(TrainingTaskSummary::from(&task))
+}
+
+// Assign the oldest pending training task to `user_id`, if any.
+#[update]
+fn claim_training_task(user_id: String) -> Result<Option<TrainingTask>, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    Ok(task_manager.claim_training_task(&user_id, ic_cdk::api::time()))
+}
+
+#[update]
+fn start_training_task(user_id: String, task_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.start_training_task(&user_id, &task_id, ic_cdk::api::time())
+}
+
+#[update]
+fn submit_training_results(
+    task_id: String,
+    model_weights: Vec<u8>,
+    num_samples: u64,
+) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_registered_user(&caller)?;
+    let user_id = consume_caller_token(&mut task_manager, &caller)?;
+    let now = ic_cdk::api::time();
+    task_manager.submit_training_results(&user_id, &task_id, model_weights, num_samples, now)
+}
+
+#[update]
+fn fail_training_task(
+    user_id: String,
+    task_id: String,
+    error: String,
+) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_user_access(&caller, &user_id)?;
+    task_manager.fail_training_task(&user_id, &task_id, error, ic_cdk::api::time())
+}
+
+#[update]
+fn validate_training_task(task_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.validate_training_task(&task_id, ic_cdk::api::time())
+}
+
+#[update]
+fn reject_training_task(task_id: String, reason: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.reject_training_task(&task_id, reason, ic_cdk::api::time())
+}
+
+#[update]
+fn cancel_training_task(task_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.cancel_training_task(&task_id, ic_cdk::api::time())
+}
+
+// Fan a model's next training round out as one task per data shard.
+#[update]
+fn start_training_round(request: TrainingRoundRequest) -> Result<TrainingRound, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.start_training_round(request, ic_cdk::api::time())
+}
+
+// Average the submitted deltas and publish them as the round's target version.
+#[update]
+fn finalize_training_round(round_id: String) -> Result<TrainingRound, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.finalize_training_round(&round_id, ic_cdk::api::time())
+}
+
+#[update]
+fn cancel_training_round(round_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.check_admin_access(&caller)?;
+    task_manager.cancel_training_round(&round_id, ic_cdk::api::time())
+}
+
+#[query]
+fn get_training_round(round_id: String) -> Result<TrainingRound, TaskManagerError> {
     TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
-        .generate_completion(&prompt)
+        .get_training_round(&round_id)
 }
 
+// Start a multipart upload of `total_size` bytes in parts of `part_size`, for payloads too large
+// for a single ingress message. Model targets need an admin; training results need the user the
+// task is assigned to, and cost a rate-limit token like `submit_training_results`.
 #[update]
-fn create_training_task(admin_token: String, task: TrainingTask) -> Result<String, String> {
+fn begin_upload(
+    target: UploadTarget,
+    total_size: u64,
+    part_size: u64,
+    content_hash: Vec<u8>,
+) -> Result<UploadStatus, TaskManagerError> {
+    let caller = authenticated_caller()?;
     let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
-    // task_manager.check_admin_access(&admin_token)?;
-    task_manager.create_training_task(task)
+    match target {
+        UploadTarget::TrainingResults { .. } => {
+            consume_caller_token(&mut task_manager, &caller)?;
+        }
+        _ => task_manager.check_admin_access(&caller)?,
+    }
+    let now = ic_cdk::api::time();
+    task_manager.begin_upload(caller, target, total_size, part_size, content_hash, now)
 }
 
 #[update]
-fn submit_training_results(task_id: String, model_weights: Vec<u8>) -> Result<(), String> {
+fn upload_part(
+    upload_id: String,
+    index: u32,
+    data: Vec<u8>,
+    part_hash: Vec<u8>,
+) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    let now = ic_cdk::api::time();
+    task_manager.upload_part(&caller, &upload_id, index, data, part_hash, now)
+}
+
+// Parts received so far, for resuming an interrupted upload.
+#[query]
+fn get_upload(upload_id: String) -> Result<UploadStatus, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_upload(&caller, &upload_id)
+}
+
+#[update]
+fn commit_upload(upload_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.commit_upload(&caller, &upload_id, ic_cdk::api::time())
+}
+
+#[update]
+fn abort_upload(upload_id: String) -> Result<(), TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let mut task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    task_manager.abort_upload(&caller, &upload_id)
+}
+
+// Read `length` bytes at `offset` of a blob. Model weights and chunks are public; submitted
+// training results are visible to admins and the user the task is assigned to.
+#[query]
+fn read_download(
+    source: DownloadSource,
+    offset: u64,
+    length: u64,
+) -> Result<DownloadSlice, TaskManagerError> {
+    let caller = authenticated_caller()?;
+    let task_manager = TASK_MANAGER.lock().map_err(handle_rwlock_poisoned)?;
+    if let DownloadSource::TrainingResults { task_id } = &source {
+        if task_manager.check_admin_access(&caller).is_err() {
+            match task_manager.get_training_task(task_id)?.assignee {
+                Some(assignee) => task_manager.check_user_access(&caller, &assignee)?,
+                None => {
+                    return Err(TaskManagerError::UnauthorizedAccess {
+                        required_role: Role::Admin,
+                    })
+                }
+            }
+        }
+    }
+    task_manager.read_download(&source, offset, length)
+}
+
+// Plain HTTP access for browsers and tools outside the IC: public downloads, e.g.
+// `GET /models/{model_id}/weights`, and the JSON API under `/api/`.
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    let task_manager = match TASK_MANAGER.lock() {
+        Ok(task_manager) => task_manager,
+        Err(error) => return HttpResponse::error(&handle_rwlock_poisoned(error)),
+    };
+    let path = request_path(&request.url);
+    if path.starts_with("/api/") {
+        return handle_api_query(&*task_manager, &request);
+    }
+    if request.method != "GET" {
+        return HttpResponse::text(405, "Only GET is supported.");
+    }
+    let source = match download_route(path) {
+        Some(source) if source.is_public() => source,
+        _ => return HttpResponse::text(404, "Not found."),
+    };
+    let callback = Func {
+        principal: ic_cdk::id(),
+        method: "http_request_streaming_callback".to_string(),
+    };
+    serve_download(&*task_manager, source, callback)
+}
+
+// JSON API requests that change state, upgraded by the gateway from `http_request`.
+#[update]
+fn http_request_update(request: HttpRequest) -> HttpResponse {
+    let mut task_manager = match TASK_MANAGER.lock() {
+        Ok(task_manager) => task_manager,
+        Err(error) => return HttpResponse::error(&handle_rwlock_poisoned(error)),
+    };
+    handle_api_update(&mut *task_manager, &request, ic_cdk::api::time())
+}
+
+#[query]
+fn http_request_streaming_callback(token: StreamingCallbackToken) -> StreamingCallbackHttpResponse {
+    match TASK_MANAGER.lock() {
+        Ok(task_manager) => stream_download(&*task_manager, token),
+        Err(_) => StreamingCallbackHttpResponse {
+            body: Vec::new(),
+            token: None,
+        },
+    }
+}
+
+#[query]
+fn get_training_tasks_by_status(
+    status: TrainingTaskStatus,
+    query: TrainingTaskQuery,
+) -> Result<TrainingTaskPage, TaskManagerError> {
+    TASK_MANAGER
+        .lock()
+        .map_err(handle_rwlock_poisoned)?
+        .get_training_tasks_by_status(status, &query)
+}
+
+#[query]
+fn get_training_tasks_by_model(
+    model_id: String,
+    query: TrainingTaskQuery,
+) -> Result<TrainingTaskPage, TaskManagerError> {
     TASK_MANAGER
         .lock()
         .map_err(handle_rwlock_poisoned)?
-        .submit_training_results(&task_id, model_weights)
+        .get_training_tasks_by_model(&model_id, &query)
 }
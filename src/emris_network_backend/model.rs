@@ -0,0 +1,193 @@
+use crate::errors::TaskManagerError;
+use crate::inference::InferenceBackend;
+use ic_cdk::export::candid::{CandidType};
+use ic_cdk::export::Principal;
+use serde::{Deserialize, Serialize};
+
+// Model family, deciding how the weights are laid out and which backends can run them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum ModelArchitecture {
+    #[default]
+    GptNeo,
+    Gpt2,
+    Bert,
+}
+
+// How the capacity reconciler treats a model; see `TaskManagerImpl::reconcile_model_capacity`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum CapacityPolicy {
+    #[default]
+    Manual,     // Only admins activate and deactivate the model
+    Degrade,    // Activated once capacity allows; marked degraded, but kept serving, below it
+    Deactivate, // Activated once capacity allows; deactivated below it
+}
+
+// Define a struct representing a model registered with the task manager.
+#[derive(Clone, Default, Deserialize, Serialize, CandidType)]
+pub struct Model {
+    pub id: String,
+    pub min_resources: u64,
+    pub active: bool,
+    #[serde(default)]
+    pub backend: InferenceBackend, // Engine used to generate completions with this model
+    #[serde(default)]
+    pub architecture: ModelArchitecture,
+    #[serde(default)]
+    pub parameter_count: u64,
+    #[serde(default)]
+    pub num_layers: u32,
+    #[serde(default)]
+    pub num_heads: u32,
+    #[serde(default)]
+    pub embedding_dim: u32,
+    #[serde(default)]
+    pub tokenizer: String, // Tokenizer name or URL, e.g. "gpt2"
+    #[serde(default)]
+    pub weight_hash: Vec<u8>, // SHA-256 of the current weights; maintained by the canister
+    #[serde(default)]
+    pub version: String, // Semantic version, e.g. "1.2.0"
+    #[serde(default)]
+    pub owner: Option<Principal>, // Principal that registered the model; set by the canister
+    #[serde(default)]
+    pub license: String, // SPDX identifier, e.g. "Apache-2.0"
+    #[serde(default)]
+    pub capacity_policy: CapacityPolicy,
+    #[serde(default)]
+    pub degraded: bool, // Active while capacity is below `min_resources`; set by the reconciler
+    #[serde(default)]
+    pub created_at: u64, // Canister time (ns) the id was first registered; set by the canister
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum ModelEventKind {
+    Activated,
+    Deactivated,
+    Degraded,
+    Recovered, // A degraded model regained enough capacity
+}
+
+// A state change made by the capacity reconciler.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub struct ModelEvent {
+    pub sequence: u64, // Increases by one per event; pass the last seen value to `get_model_events`
+    pub model_id: String,
+    pub kind: ModelEventKind,
+    pub capacity: u64,      // Resources available to the model when the event fired
+    pub min_resources: u64,
+    pub timestamp: u64,
+}
+
+// Largest page returned by the paginated model listings.
+pub const MAX_MODEL_PAGE_SIZE: u32 = 100;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum ModelSortKey {
+    #[default]
+    Id,
+    CreatedAt,
+    MinResources,
+}
+
+// Page request for model listings. Models are ordered by `sort_by`, ties broken by id, so a
+// cursor keeps its place even when models are added or removed between calls.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, CandidType)]
+pub struct ModelQuery {
+    pub cursor: Option<String>, // `next_cursor` of the previous page; `None` for the first page
+    pub limit: u32,             // At most `MAX_MODEL_PAGE_SIZE`
+    pub sort_by: ModelSortKey,
+    pub descending: bool,
+    pub architecture: Option<ModelArchitecture>,
+    pub backend: Option<InferenceBackend>,
+    pub owner: Option<Principal>,
+}
+
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct ModelPage {
+    pub models: Vec<Model>,
+    pub next_cursor: Option<String>, // `None` once the last page has been returned
+    pub total: u64,                  // Models matching the filters, across all pages
+}
+
+// A published version of a model, kept so admins can promote it or roll back to it.
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct ModelVersion {
+    pub model: Model,       // Registry entry as published; `weight_hash` describes its weights
+    pub published_at: u64, // Canister time (ns) the version was registered
+}
+
+// Parse MAJOR.MINOR.PATCH, ignoring any pre-release or build suffix, as described on semver.org.
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.split(['-', '+']).next()?;
+    let mut parts = core.split('.').map(|part| {
+        let numeric = !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+        // Leading zeros are not allowed.
+        if !numeric || (part.len() > 1 && part.starts_with('0')) {
+            return None;
+        }
+        part.parse().ok()
+    });
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(version)
+}
+
+impl ModelQuery {
+    pub fn matches(&self, model: &Model) -> bool {
+        self.architecture.is_none_or(|architecture| model.architecture == architecture)
+            && self.backend.is_none_or(|backend| model.backend == backend)
+            && self.owner.is_none_or(|owner| model.owner == Some(owner))
+    }
+
+    // Position of `model` in the requested order, before `descending` is applied.
+    pub fn sort_key(&self, model: &Model) -> (u64, String) {
+        let key = match self.sort_by {
+            ModelSortKey::Id => 0,
+            ModelSortKey::CreatedAt => model.created_at,
+            ModelSortKey::MinResources => model.min_resources,
+        };
+        (key, model.id.clone())
+    }
+
+    // Cursors are the sort key of the last model returned, as "<key>:<id>".
+    pub fn encode_cursor((key, id): &(u64, String)) -> String {
+        format!("{}:{}", key, id)
+    }
+
+    pub fn decode_cursor(cursor: &str) -> Result<(u64, String), TaskManagerError> {
+        cursor
+            .split_once(':')
+            .and_then(|(key, id)| Some((key.parse().ok()?, id.to_string())))
+            .ok_or_else(|| TaskManagerError::invalid_argument("cursor", "is malformed"))
+    }
+}
+
+impl Model {
+    pub fn validate(&self) -> Result<(), TaskManagerError> {
+        if self.id.is_empty() {
+            return Err(TaskManagerError::invalid_argument("id", "cannot be empty"));
+        }
+        if parse_version(&self.version).is_none() {
+            return Err(TaskManagerError::invalid_argument(
+                "version",
+                "must be a semantic version such as 1.0.0",
+            ));
+        }
+        if self.num_heads > 0 && !self.embedding_dim.is_multiple_of(self.num_heads) {
+            return Err(TaskManagerError::invalid_argument(
+                "embedding_dim",
+                "must be divisible by num_heads",
+            ));
+        }
+        if self.backend == InferenceBackend::GptNeo
+            && self.architecture != ModelArchitecture::GptNeo
+        {
+            return Err(TaskManagerError::invalid_argument(
+                "backend",
+                "the GPT-Neo backend only runs GPT-Neo models",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -1,12 +1,92 @@
+use crate::errors::TaskManagerError;
 use ic_cdk::export::candid::{CandidType};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 // Define a struct representing a chunk of a larger data model.
 #[derive(Clone, Deserialize, Serialize, CandidType)]
 pub struct ModelChunk {
     pub id: String,      // Unique identifier for this chunk
+    #[serde(default)]
+    pub model_id: String, // Identifier of the model this chunk was sharded from
     pub user_id: String, // Identifier of the user associated with this chunk
+    #[serde(with = "serde_bytes")]
     pub data: Vec<u8>,   // Binary data representing the content of this chunk
-                         // TODO: Consider adding metadata (e.g., timestamp, chunk size) to the struct.
-                         // TODO: Implement logic for combining chunks to reconstruct the complete model.
+    #[serde(default)]
+    pub index: u32,      // Position of this chunk within the model (0-based)
+    #[serde(default)]
+    pub total_chunks: u32, // Number of chunks the model was split into
+    #[serde(default)]
+    pub replica: u32,    // Which of the redundant copies of this index this chunk is
+    #[serde(default)]
+    pub offset: u64,     // Byte offset of `data` within the complete model
+    #[serde(default)]
+    pub content_hash: Vec<u8>, // SHA-256 of `data`, checked on reassembly
+    #[serde(default)]
+    pub lease_expires_at: Option<u64>, // Canister time (ns) the holder's lease runs out; None once submitted
+}
+
+// SHA-256 digest used for chunk and model integrity checks.
+pub fn content_hash(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+impl ModelChunk {
+    // Identifies the shard shared by every replica of this chunk index.
+    pub fn shard_id(&self) -> String {
+        format!("{}:{}", self.model_id, self.index)
+    }
+
+    pub fn verify_hash(&self) -> bool {
+        self.content_hash == content_hash(&self.data)
+    }
+}
+
+// Combine the chunks of a single model back into its complete weight blob. Every chunk must be
+// present exactly once, agree on the total count, pass its hash check and sit at the byte offset
+// where the previous chunk ended.
+pub fn reassemble_chunks(
+    model_id: &str,
+    chunks: &[&ModelChunk],
+) -> Result<Vec<u8>, TaskManagerError> {
+    if chunks.is_empty() {
+        return Err(TaskManagerError::IncompleteModel {
+            model_id: model_id.to_string(),
+            present: 0,
+            expected: 0,
+        });
+    }
+    let total_chunks = chunks[0].total_chunks;
+    if let Some(chunk) = chunks.iter().find(|chunk| chunk.total_chunks != total_chunks) {
+        return Err(TaskManagerError::ChunkIntegrityFailed {
+            chunk_id: chunk.id.clone(),
+        });
+    }
+    if chunks.len() != total_chunks as usize {
+        return Err(TaskManagerError::IncompleteModel {
+            model_id: model_id.to_string(),
+            present: chunks.len() as u32,
+            expected: total_chunks,
+        });
+    }
+
+    let mut ordered = chunks.to_vec();
+    ordered.sort_by_key(|chunk| chunk.index);
+    let mut model = Vec::new();
+    for (expected_index, chunk) in ordered.into_iter().enumerate() {
+        if chunk.index as usize != expected_index {
+            return Err(TaskManagerError::MissingChunk {
+                model_id: model_id.to_string(),
+                index: expected_index as u32,
+            });
+        }
+        // A chunk at the wrong offset or with a stale hash has been tampered with or corrupted.
+        if chunk.offset != model.len() as u64 || !chunk.verify_hash() {
+            return Err(TaskManagerError::ChunkIntegrityFailed {
+                chunk_id: chunk.id.clone(),
+            });
+        }
+        model.extend_from_slice(&chunk.data);
+    }
+    Ok(model)
 }
@@ -0,0 +1,120 @@
+use crate::completion::{CompletionRequest, FinishReason};
+use crate::errors::TaskManagerError;
+use crate::inference::{
+    find_stop, log_probability, sample_token, Generation, SamplingRng, TextGenerator,
+};
+use crate::tokenizer::GPT2Tokenizer;
+use futures::executor::block_on;
+use std::collections::HashMap;
+use wonnx::utils::{InputTensor, OutputTensor};
+use wonnx::Session;
+
+// GPT-2 vocabulary id of `<|endoftext|>`, shared by every model using its tokenizer.
+const END_OF_TEXT_TOKEN: i64 = 50256;
+
+// Runs a causal language model exported to ONNX with an `input_ids` input and a `logits`
+// output, sampling each token with `inference::sample_token`.
+pub struct OnnxTextGenerator {
+    session: Session,
+    tokenizer: GPT2Tokenizer,
+}
+
+// Logits of the last position. Graphs export either every position's logits, `[1, seq, vocab]`,
+// or only the last one's, `[1, vocab]`; wonnx does not report the output shape, so the two are
+// told apart by `min_vocab_size`, the tokenizer's vocabulary size.
+pub fn last_position_logits(
+    mut logits: Vec<f32>,
+    positions: usize,
+    min_vocab_size: usize,
+) -> Result<Vec<f32>, TaskManagerError> {
+    let positions = positions.max(1);
+    let per_position = logits.len() / positions;
+    let vocab_size = if logits.len().is_multiple_of(positions) && per_position >= min_vocab_size {
+        per_position
+    } else {
+        logits.len()
+    };
+    if vocab_size == 0 || vocab_size < min_vocab_size {
+        return Err(inference_failed(format!(
+            "'logits' output of {} values does not fit {} tokens and a vocabulary of {}",
+            logits.len(),
+            positions,
+            min_vocab_size
+        )));
+    }
+    Ok(logits.split_off(logits.len() - vocab_size))
+}
+
+fn inference_failed(error: impl std::fmt::Display) -> TaskManagerError {
+    TaskManagerError::InferenceFailed(error.to_string())
+}
+
+impl OnnxTextGenerator {
+    pub fn from_bytes(model: &[u8], tokenizer_json: &[u8]) -> Result<Self, TaskManagerError> {
+        let tokenizer = GPT2Tokenizer::from_bytes(tokenizer_json)?;
+        let session = block_on(Session::from_bytes(model)).map_err(inference_failed)?;
+        Ok(Self { session, tokenizer })
+    }
+
+    // Logits of the position following `ids`.
+    fn next_logits(&self, ids: &[i64]) -> Result<Vec<f32>, TaskManagerError> {
+        let mut inputs = HashMap::new();
+        inputs.insert("input_ids".to_string(), InputTensor::from(ids));
+        let mut outputs = block_on(self.session.run(&inputs)).map_err(inference_failed)?;
+        let logits = match outputs.remove("logits") {
+            Some(OutputTensor::F32(logits)) if !logits.is_empty() => logits,
+            _ => return Err(inference_failed("model has no f32 'logits' output")),
+        };
+        last_position_logits(logits, ids.len(), self.tokenizer.vocab_size())
+    }
+}
+
+impl TextGenerator for OnnxTextGenerator {
+    fn generate(&self, request: &CompletionRequest) -> Result<Generation, TaskManagerError> {
+        let mut ids: Vec<i64> = self
+            .tokenizer
+            .encode(&request.prompt)?
+            .into_iter()
+            .map(i64::from)
+            .collect();
+        if ids.is_empty() {
+            return Err(TaskManagerError::invalid_argument(
+                "prompt",
+                "must encode to at least one token",
+            ));
+        }
+        let prompt_tokens = ids.len();
+        let mut rng = SamplingRng::new(request.seed.unwrap_or_default());
+        let mut text = String::new();
+        let mut token_logprobs = Vec::new();
+        let mut finish_reason = FinishReason::Length;
+        for _ in 0..request.max_new_tokens {
+            let logits = self.next_logits(&ids)?;
+            let token = sample_token(&logits, &ids, request, &mut rng);
+            if token as i64 == END_OF_TEXT_TOKEN {
+                finish_reason = FinishReason::EndOfText;
+                break;
+            }
+            if request.logprobs {
+                token_logprobs.push(log_probability(&logits, token));
+            }
+            ids.push(token as i64);
+
+            let completion: Vec<u32> = ids[prompt_tokens..].iter().map(|&id| id as u32).collect();
+            text = self.tokenizer.decode(&completion)?;
+            if let Some(stop) = find_stop(&text, &request.stop_sequences) {
+                text.truncate(stop);
+                finish_reason = FinishReason::StopSequence;
+                break;
+            }
+        }
+
+        Ok(Generation {
+            text,
+            prompt_tokens: prompt_tokens as u32,
+            completion_tokens: (ids.len() - prompt_tokens) as u32,
+            token_logprobs: request.logprobs.then_some(token_logprobs),
+            finish_reason,
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use crate::errors::TaskManagerError;
+use crate::user::User;
+use ic_cdk::export::candid::{CandidType};
+use serde::{Deserialize, Serialize};
+
+const SECOND_NS: u64 = 1_000_000_000;
+
+// Service tier deciding how large a user's token bucket is and how fast it refills.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum RateLimitTier {
+    #[default]
+    Free,
+    Contributor,
+    Premium,
+}
+
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct TierLimits {
+    pub capacity: u64,           // Maximum number of tokens the bucket holds
+    pub refill_interval_ns: u64, // Time it takes to regain a single token
+}
+
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct RateLimitConfig {
+    pub free: TierLimits,
+    pub contributor: TierLimits,
+    pub premium: TierLimits,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            free: TierLimits {
+                capacity: 10,
+                refill_interval_ns: 60 * SECOND_NS,
+            },
+            contributor: TierLimits {
+                capacity: 60,
+                refill_interval_ns: 10 * SECOND_NS,
+            },
+            premium: TierLimits {
+                capacity: 600,
+                refill_interval_ns: SECOND_NS,
+            },
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn limits(&self, tier: RateLimitTier) -> &TierLimits {
+        match tier {
+            RateLimitTier::Free => &self.free,
+            RateLimitTier::Contributor => &self.contributor,
+            RateLimitTier::Premium => &self.premium,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), TaskManagerError> {
+        for limits in [&self.free, &self.contributor, &self.premium] {
+            if limits.capacity == 0 || limits.refill_interval_ns == 0 {
+                return Err(TaskManagerError::invalid_argument(
+                    "config",
+                    "tier capacity and refill interval must be greater than zero",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Top up the user's bucket for the time elapsed since the last refill. Partial intervals carry
+// over so a caller polling faster than the refill rate still earns tokens on schedule.
+fn refill(user: &mut User, limits: &TierLimits, now: u64) {
+    let elapsed = now.saturating_sub(user.rate_limit_refilled_at);
+    let earned = elapsed / limits.refill_interval_ns;
+    let tokens = user.rate_limit_tokens.saturating_add(earned);
+    if tokens >= limits.capacity {
+        user.rate_limit_tokens = limits.capacity;
+        user.rate_limit_refilled_at = now;
+    } else {
+        user.rate_limit_tokens = tokens;
+        user.rate_limit_refilled_at += earned * limits.refill_interval_ns;
+    }
+}
+
+// Take one token from the user's bucket. On failure returns how many nanoseconds remain until
+// the next token becomes available.
+pub fn try_consume(user: &mut User, limits: &TierLimits, now: u64) -> Result<u64, u64> {
+    refill(user, limits, now);
+    if user.rate_limit_tokens == 0 {
+        let elapsed = now.saturating_sub(user.rate_limit_refilled_at);
+        return Err(limits.refill_interval_ns.saturating_sub(elapsed));
+    }
+    user.rate_limit_tokens -= 1;
+    Ok(user.rate_limit_tokens)
+}
@@ -0,0 +1,65 @@
+use ic_cdk::export::candid::{CandidType};
+use serde::{Deserialize, Serialize};
+
+// Scores are expressed in basis points so they stay integral across Candid and stable memory.
+pub const MAX_REPUTATION_SCORE: u32 = 10_000;
+
+// A wrong result counts against a user more heavily than a missed deadline.
+const REJECTED_WEIGHT: u64 = 3;
+const TIMED_OUT_WEIGHT: u64 = 1;
+
+// Share of accrued rewards removed each time verification proves a user's result wrong.
+pub const REWARD_SLASH_PERCENT: u64 = 10;
+
+// Define a struct tracking how reliably a user has computed the chunks assigned to them.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub struct Reputation {
+    pub accepted: u64,  // Submissions that matched the accepted answer
+    pub rejected: u64,  // Submissions that verification proved wrong
+    pub timed_out: u64, // Leases that expired without a submission
+    pub score: u32,     // Derived from the counters; see `Reputation::recompute`
+}
+
+impl Default for Reputation {
+    fn default() -> Self {
+        let mut reputation = Self {
+            accepted: 0,
+            rejected: 0,
+            timed_out: 0,
+            score: 0,
+        };
+        reputation.recompute();
+        reputation
+    }
+}
+
+impl Reputation {
+    pub fn record_accepted(&mut self) {
+        self.accepted += 1;
+        self.recompute();
+    }
+
+    pub fn record_rejected(&mut self) {
+        self.rejected += 1;
+        self.recompute();
+    }
+
+    pub fn record_timed_out(&mut self) {
+        self.timed_out += 1;
+        self.recompute();
+    }
+
+    // Smoothed ratio of good outcomes to weighted outcomes, so a new user starts at half the
+    // maximum and a single result moves the score only a little.
+    fn recompute(&mut self) {
+        let good = self.accepted as u128 + 1;
+        let bad = (self.rejected * REJECTED_WEIGHT + self.timed_out * TIMED_OUT_WEIGHT) as u128 + 1;
+        self.score = (MAX_REPUTATION_SCORE as u128 * good / (good + bad)) as u32;
+    }
+}
+
+// Amount to remove from `rewards` when a user is slashed; never less than one token while any
+// rewards remain.
+pub fn slash_amount(rewards: u64) -> u64 {
+    ((rewards as u128 * REWARD_SLASH_PERCENT as u128 / 100) as u64).max(rewards.min(1))
+}
@@ -0,0 +1,143 @@
+use crate::task_manager_impl::TaskManagerImpl;
+use ic_cdk::api::stable::{
+    stable_size, BufferedStableReader, BufferedStableWriter, CanisterStableMemory, StableWriter,
+};
+use std::io::{Read, Write};
+
+// Layout version written by `save_state`. Adding a field to `User`, `Model`, `ModelChunk` or
+// `TrainingTask` does not need a bump as long as the field carries `#[serde(default)]` (or is an
+// `Option`); renames, removals and type changes must bump this and add an arm to `migrate_state`.
+// Version 2 stores blobs as CBOR byte strings rather than arrays of integers.
+pub const STATE_VERSION: u32 = 2;
+
+// Header stored ahead of the payload: version (u32, LE) followed by payload length (u64, LE).
+const HEADER_LEN: usize = 12;
+
+// Bytes `save_state` and `restore_state` buffer between accesses to stable memory.
+const IO_BUFFER_SIZE: usize = 1 << 20;
+
+// `serde_bytes` for `Option<Vec<u8>>` fields, which the Candid derive does not accept under
+// `#[serde(with = "serde_bytes")]`.
+pub mod optional_blob {
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S>(blob: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serde_bytes::serialize(blob, serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        serde_bytes::deserialize(deserializer)
+    }
+}
+
+// `#[serde(with = "crate::stable_state::blob_map")]` stores every value of a map of blobs as a
+// byte string, as `serde_bytes` does for single blob fields; plain `Vec<u8>` values take about
+// twice their size in CBOR.
+pub mod blob_map {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_bytes::{ByteBuf, Bytes};
+    use std::collections::BTreeMap;
+    use std::iter::FromIterator;
+
+    pub fn serialize<M, K, S>(map: &M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        for<'a> &'a M: IntoIterator<Item = (&'a K, &'a Vec<u8>)>,
+        K: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_map(map.into_iter().map(|(key, value)| (key, Bytes::new(value))))
+    }
+
+    pub fn deserialize<'de, M, K, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: FromIterator<(K, Vec<u8>)>,
+        K: Deserialize<'de> + Ord,
+        D: Deserializer<'de>,
+    {
+        let map = BTreeMap::<K, ByteBuf>::deserialize(deserializer)?;
+        Ok(map.into_iter().map(|(key, value)| (key, value.into_vec())).collect())
+    }
+}
+
+fn encode_header(payload_len: u64) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[0..4].copy_from_slice(&STATE_VERSION.to_le_bytes());
+    header[4..HEADER_LEN].copy_from_slice(&payload_len.to_le_bytes());
+    header
+}
+
+// Serialize the task manager into the blob `save_state` writes to stable memory.
+#[cfg(test)]
+pub fn encode_state(task_manager: &TaskManagerImpl) -> Result<Vec<u8>, String> {
+    let payload = serde_cbor::to_vec(task_manager)
+        .map_err(|e| format!("Failed to serialize task manager state: {}", e))?;
+    let mut bytes = encode_header(payload.len() as u64).to_vec();
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+// Decode a blob written by `encode_state`, migrating it from older layouts when needed.
+#[cfg(test)]
+pub fn decode_state(bytes: &[u8]) -> Result<TaskManagerImpl, String> {
+    read_state(bytes)
+}
+
+// Read a header and the payload that follows it from `reader`, decoding the payload as it is
+// read so it is never held in memory next to the state it decodes to.
+fn read_state(mut reader: impl Read) -> Result<TaskManagerImpl, String> {
+    let mut header = [0u8; HEADER_LEN];
+    reader
+        .read_exact(&mut header)
+        .map_err(|_| "Stable state is truncated.".to_string())?;
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&header[0..4]);
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[4..HEADER_LEN]);
+    let payload = reader.take(u64::from_le_bytes(len));
+    let mut task_manager = migrate_state(u32::from_le_bytes(version), payload)?;
+    task_manager.backfill_model_versions();
+    task_manager.backfill_results_hashes();
+    Ok(task_manager)
+}
+
+// Bring a payload written under `version` up to the current `TaskManagerImpl` layout.
+fn migrate_state(version: u32, payload: impl Read) -> Result<TaskManagerImpl, String> {
+    match version {
+        // Version 1 blobs are arrays of integers, which the byte string fields also accept.
+        1 | 2 => serde_cbor::from_reader(payload)
+            .map_err(|e| format!("Failed to deserialize task manager state: {}", e)),
+        _ => Err(format!("Unsupported stable state version {}.", version)),
+    }
+}
+
+// Stream the task manager into stable memory. The state can be a large share of the heap, so it
+// is never serialized into a buffer of its own: the payload goes straight in after room left
+// for the header, which is written once the payload length is known.
+pub fn save_state(task_manager: &TaskManagerImpl) -> Result<(), String> {
+    let payload_writer = StableWriter::with_memory(CanisterStableMemory::default(), HEADER_LEN);
+    let mut writer = BufferedStableWriter::with_writer(IO_BUFFER_SIZE, payload_writer);
+    serde_cbor::to_writer(&mut writer, task_manager)
+        .map_err(|e| format!("Failed to serialize task manager state: {}", e))?;
+    writer
+        .flush()
+        .map_err(|e| format!("Failed to write stable memory: {}", e))?;
+    let payload_len = (writer.offset() - HEADER_LEN) as u64;
+    StableWriter::default()
+        .write_all(&encode_header(payload_len))
+        .map_err(|e| format!("Failed to write stable memory: {}", e))
+}
+
+// Restore the task manager saved by `save_state`. A canister that never saved state (e.g. one
+// upgraded from a build without persistence) has empty stable memory and starts fresh.
+pub fn restore_state() -> Result<TaskManagerImpl, String> {
+    if stable_size() == 0 {
+        return Ok(TaskManagerImpl::default());
+    }
+    read_state(BufferedStableReader::new(IO_BUFFER_SIZE))
+}
@@ -1,33 +1,257 @@
-use crate::completion::Completion;
+use crate::completion::{Completion, CompletionRequest};
+use crate::completion_job::{CompletionJob, CompletionJobStatus, CompletionJobUpdate};
+use crate::download::{DownloadSlice, DownloadSource};
+use crate::errors::TaskManagerError;
+use crate::model::{
+    CapacityPolicy, Model, ModelArchitecture, ModelEvent, ModelPage, ModelQuery, ModelVersion,
+};
 use crate::model_chunk::ModelChunk;
-use crate::task_manager_impl::Model;
-use crate::training_task::TrainingTask;
-use crate::user::User;
+use crate::training_round::{TrainingRound, TrainingRoundRequest};
+use crate::training_task::{TrainingTask, TrainingTaskPage, TrainingTaskQuery, TrainingTaskStatus};
+use crate::rate_limit::{RateLimitConfig, RateLimitTier};
+use crate::reputation::Reputation;
+use crate::upload::{UploadStatus, UploadTarget};
+use crate::user::{ResourcePledge, User};
+use crate::verification::{ShardVerification, VerificationConfig};
+use ic_cdk::export::Principal;
 // use ic_cdk::export::candid::CandidType;
 // use std::collections::HashMap;
 
 pub trait TaskManagerInterface {
-    fn init(&mut self);
-    fn register_user(&mut self, user: User) -> Result<String, String>;
-    fn update_user_resources(&mut self, id: &str, resources: u64) -> Result<(), String>;
-    fn distribute_model_chunks(&mut self) -> Result<(), String>;
+    fn init(&mut self, controller: Principal);
+    fn check_controller_access(&self, caller: &Principal) -> Result<(), TaskManagerError>;
+    fn check_admin_access(&self, caller: &Principal) -> Result<(), TaskManagerError>;
+    fn check_user_access(&self, caller: &Principal, user_id: &str) -> Result<(), TaskManagerError>;
+    fn check_registered_user(&self, caller: &Principal) -> Result<(), TaskManagerError>;
+    fn add_controller(&mut self, principal: Principal) -> Result<(), TaskManagerError>;
+    fn remove_controller(&mut self, principal: &Principal) -> Result<(), TaskManagerError>;
+    fn add_admin(&mut self, principal: Principal) -> Result<(), TaskManagerError>;
+    fn remove_admin(&mut self, principal: &Principal) -> Result<(), TaskManagerError>;
+    fn get_controllers(&self) -> Vec<Principal>;
+    fn get_admins(&self) -> Vec<Principal>;
+    fn find_user_id(&self, caller: &Principal) -> Result<String, TaskManagerError>;
+    fn consume_rate_limit_token(
+        &mut self,
+        user_id: &str,
+        now: u64,
+    ) -> Result<u64, TaskManagerError>;
+    fn set_user_tier(&mut self, user_id: &str, tier: RateLimitTier) -> Result<(), TaskManagerError>;
+    fn set_rate_limit_config(&mut self, config: RateLimitConfig) -> Result<(), TaskManagerError>;
+    fn get_rate_limit_config(&self) -> RateLimitConfig;
+    fn register_user(&mut self, user: User) -> Result<String, TaskManagerError>;
+    fn update_user_resources(&mut self, id: &str, resources: u64) -> Result<(), TaskManagerError>;
+    fn pledge_resources(
+        &mut self,
+        user_id: &str,
+        model_id: &str,
+        amount: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn get_user_pledges(&self, user_id: &str) -> Result<Vec<ResourcePledge>, TaskManagerError>;
+    fn get_model_capacity(&self, model_id: &str) -> Result<u64, TaskManagerError>;
+    fn upload_model_weights(
+        &mut self,
+        model_id: &str,
+        weights: Vec<u8>,
+    ) -> Result<(), TaskManagerError>;
+    fn upload_model_tokenizer(
+        &mut self,
+        model_id: &str,
+        tokenizer: Vec<u8>,
+    ) -> Result<(), TaskManagerError>;
+    fn distribute_model_chunks(
+        &mut self,
+        model_id: &str,
+        chunk_size: u64,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
     fn submit_computed_chunk(
         &mut self,
         chunk: ModelChunk,
         computed_results: Vec<u32>, // Add this parameter
-    ) -> Result<(), String>;
-    fn get_model_chunks(&self, user_id: &str) -> Result<Vec<ModelChunk>, String>;
-    fn get_rewards(&self, user_id: &str) -> Result<u64, String>;
-    fn register_model(&mut self, model: Model) -> Result<String, String>;
-    fn activate_model(&mut self, model_id: &str) -> Result<(), String>;
-    fn deactivate_model(&mut self, model_id: &str) -> Result<(), String>;
-    fn get_active_models(&self, offset: usize, limit: usize) -> Vec<Model>;
-    fn get_models_needing_resources(&self, offset: usize, limit: usize) -> Vec<Model>;
-    fn generate_completion(&self, prompt: &str) -> Result<Completion, String>;
-    fn create_training_task(&mut self, task: TrainingTask) -> Result<String, String>;
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn get_reputation(&self, user_id: &str) -> Result<Reputation, TaskManagerError>;
+    fn get_shard_verification(&self, shard_id: &str) -> Result<ShardVerification, TaskManagerError>;
+    fn set_verification_config(
+        &mut self,
+        config: VerificationConfig,
+    ) -> Result<(), TaskManagerError>;
+    fn get_verification_config(&self) -> VerificationConfig;
+    fn renew_chunk_lease(
+        &mut self,
+        user_id: &str,
+        chunk_id: &str,
+        now: u64,
+    ) -> Result<u64, TaskManagerError>;
+    fn reclaim_expired_leases(&mut self, now: u64) -> Vec<String>;
+    fn get_model_chunks(&self, user_id: &str) -> Result<Vec<ModelChunk>, TaskManagerError>;
+    fn get_reassembled_model(&self, model_id: &str) -> Result<Vec<u8>, TaskManagerError>;
+    fn reassemble_model(&mut self, model_id: &str) -> Result<Vec<u8>, TaskManagerError>;
+    fn get_rewards(&self, user_id: &str) -> Result<u64, TaskManagerError>;
+    fn add_api_key(&mut self, user_id: &str, key: &str) -> Result<(), TaskManagerError>;
+    fn revoke_api_keys(&mut self, user_id: &str) -> Result<u32, TaskManagerError>;
+    fn find_api_key_user(&self, key: &str) -> Result<String, TaskManagerError>;
+    fn register_model(&mut self, model: Model, now: u64) -> Result<String, TaskManagerError>;
+    fn upload_model_version_weights(
+        &mut self,
+        model_id: &str,
+        version: &str,
+        weights: Vec<u8>,
+    ) -> Result<(), TaskManagerError>;
+    fn get_model_versions(&self, model_id: &str) -> Result<Vec<ModelVersion>, TaskManagerError>;
+    fn promote_model_version(
+        &mut self,
+        model_id: &str,
+        version: &str,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn rollback_model(&mut self, model_id: &str, now: u64) -> Result<String, TaskManagerError>;
+    fn get_model(&self, model_id: &str) -> Result<Model, TaskManagerError>;
+    fn get_models_by_architecture(
+        &self,
+        architecture: ModelArchitecture,
+        query: &ModelQuery,
+    ) -> Result<ModelPage, TaskManagerError>;
+    fn activate_model(&mut self, model_id: &str) -> Result<(), TaskManagerError>;
+    fn deactivate_model(&mut self, model_id: &str) -> Result<(), TaskManagerError>;
+    fn set_model_capacity_policy(
+        &mut self,
+        model_id: &str,
+        policy: CapacityPolicy,
+    ) -> Result<(), TaskManagerError>;
+    fn reconcile_model_capacity(&mut self, now: u64) -> Vec<ModelEvent>;
+    fn get_model_events(&self, after: Option<u64>, limit: usize) -> Vec<ModelEvent>;
+    fn get_active_models(&self, query: &ModelQuery) -> Result<ModelPage, TaskManagerError>;
+    fn get_models_needing_resources(
+        &self,
+        query: &ModelQuery,
+    ) -> Result<ModelPage, TaskManagerError>;
+    fn load_inference_backend(&mut self, model_id: &str) -> Result<(), TaskManagerError>;
+    fn generate_completion(
+        &mut self,
+        user_id: &str,
+        request: &CompletionRequest,
+        now: u64,
+    ) -> Result<Completion, TaskManagerError>;
+    fn get_completion(&self, completion_id: &str) -> Result<Completion, TaskManagerError>;
+    fn submit_completion_job(
+        &mut self,
+        user_id: &str,
+        request: CompletionRequest,
+        now: u64,
+    ) -> Result<String, TaskManagerError>;
+    fn claim_completion_job(
+        &mut self,
+        worker_id: &str,
+        now: u64,
+    ) -> Result<Option<CompletionJob>, TaskManagerError>;
+    fn post_completion_job_update(
+        &mut self,
+        worker_id: &str,
+        job_id: &str,
+        update: CompletionJobUpdate,
+        now: u64,
+    ) -> Result<CompletionJobStatus, TaskManagerError>;
+    fn review_completion_job(
+        &mut self,
+        job_id: &str,
+        accepted: bool,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn get_completion_job(&self, job_id: &str) -> Result<CompletionJob, TaskManagerError>;
+    fn create_training_task(
+        &mut self,
+        task: TrainingTask,
+        now: u64,
+    ) -> Result<String, TaskManagerError>;
+    fn get_training_task(&self, task_id: &str) -> Result<TrainingTask, TaskManagerError>;
+    fn claim_training_task(&mut self, user_id: &str, now: u64) -> Option<TrainingTask>;
+    fn start_training_task(
+        &mut self,
+        user_id: &str,
+        task_id: &str,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
     fn submit_training_results(
         &mut self,
+        user_id: &str,
         task_id: &str,
         model_weights: Vec<u8>,
-    ) -> Result<(), String>;
+        num_samples: u64,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn fail_training_task(
+        &mut self,
+        user_id: &str,
+        task_id: &str,
+        error: String,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn validate_training_task(&mut self, task_id: &str, now: u64) -> Result<(), TaskManagerError>;
+    fn reject_training_task(
+        &mut self,
+        task_id: &str,
+        reason: String,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn cancel_training_task(&mut self, task_id: &str, now: u64) -> Result<(), TaskManagerError>;
+    fn get_training_tasks_by_status(
+        &self,
+        status: TrainingTaskStatus,
+        query: &TrainingTaskQuery,
+    ) -> Result<TrainingTaskPage, TaskManagerError>;
+    fn get_training_tasks_by_model(
+        &self,
+        model_id: &str,
+        query: &TrainingTaskQuery,
+    ) -> Result<TrainingTaskPage, TaskManagerError>;
+    fn start_training_round(
+        &mut self,
+        request: TrainingRoundRequest,
+        now: u64,
+    ) -> Result<TrainingRound, TaskManagerError>;
+    fn finalize_training_round(
+        &mut self,
+        round_id: &str,
+        now: u64,
+    ) -> Result<TrainingRound, TaskManagerError>;
+    fn cancel_training_round(&mut self, round_id: &str, now: u64) -> Result<(), TaskManagerError>;
+    fn get_training_round(&self, round_id: &str) -> Result<TrainingRound, TaskManagerError>;
+    fn begin_upload(
+        &mut self,
+        owner: Principal,
+        target: UploadTarget,
+        total_size: u64,
+        part_size: u64,
+        content_hash: Vec<u8>,
+        now: u64,
+    ) -> Result<UploadStatus, TaskManagerError>;
+    fn upload_part(
+        &mut self,
+        caller: &Principal,
+        upload_id: &str,
+        index: u32,
+        data: Vec<u8>,
+        part_hash: Vec<u8>,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn get_upload(
+        &self,
+        caller: &Principal,
+        upload_id: &str,
+    ) -> Result<UploadStatus, TaskManagerError>;
+    fn commit_upload(
+        &mut self,
+        caller: &Principal,
+        upload_id: &str,
+        now: u64,
+    ) -> Result<(), TaskManagerError>;
+    fn abort_upload(&mut self, caller: &Principal, upload_id: &str) -> Result<(), TaskManagerError>;
+    fn drop_expired_uploads(&mut self, now: u64) -> usize;
+    fn read_download(
+        &self,
+        source: &DownloadSource,
+        offset: u64,
+        length: u64,
+    ) -> Result<DownloadSlice, TaskManagerError>;
 }
\ No newline at end of file
@@ -1,72 +1,400 @@
-use crate::completion::Completion;
-use crate::model_chunk::ModelChunk;
+use crate::completion::{Completion, CompletionRequest, FinishReason};
+use crate::completion_job::{
+    CompletionJob, CompletionJobStatus, CompletionJobUpdate, COMPLETION_JOB_EXPIRY_NS,
+    COMPLETION_JOB_LEASE_DURATION_NS, MAX_COMPLETION_BYTES_PER_TOKEN, MAX_PENDING_COMPLETION_JOBS,
+};
+use crate::download::{slice_range, DownloadSlice, DownloadSource};
+use crate::errors::{Role, TaskManagerError};
+use crate::inference::{find_stop, load_text_generator, InferenceBackend, TextGenerator};
+use crate::model::{
+    parse_version, CapacityPolicy, Model, ModelArchitecture, ModelEvent, ModelEventKind,
+    ModelPage, ModelQuery, ModelVersion, MAX_MODEL_PAGE_SIZE,
+};
+use crate::model_chunk::{content_hash, reassemble_chunks, ModelChunk};
 use crate::task_manager::TaskManagerInterface;
-use crate::training_task::TrainingTask;
-use crate::user::User;
-use ic_cdk::export::candid::CandidType;
+use crate::tokenizer::GPT2Tokenizer;
+use crate::training_round::{
+    aggregate, decode_weights, encode_weights, TrainingRound, TrainingRoundRequest,
+    TrainingRoundStatus,
+};
+use crate::training_task::{
+    TrainingTask, TrainingTaskPage, TrainingTaskQuery, TrainingTaskStatus, TrainingTaskSummary,
+    MAX_NUM_SAMPLES, MAX_TRAINING_TASK_PAGE_SIZE,
+};
+use crate::rate_limit::{try_consume, RateLimitConfig, RateLimitTier};
+use crate::reputation::{slash_amount, Reputation};
+use crate::upload::{
+    validate_upload_layout, Upload, UploadStatus, UploadTarget, MAX_OPEN_UPLOADS_PER_OWNER,
+    MAX_PENDING_UPLOAD_BYTES,
+};
+use crate::user::{ResourcePledge, User};
+use crate::verification::{ShardVerification, VerificationConfig, VerificationStatus};
+use ic_cdk::export::Principal;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
 
-// use crate::gpt_neo::GptNeoTextGenerator;
-// use rust_bert::gpt2::{Gpt2Config, Gpt2ForGeneration, Gpt2Tokenizer};
-// use rust_bert::pipelines::generation::{GenerateConfig, LanguageGenerator};
+// How long a user holds a chunk before it is reclaimed, unless renewed via heartbeat.
+pub const CHUNK_LEASE_DURATION_NS: u64 = 10 * 60 * 1_000_000_000;
 
+// Capacity transitions kept for `get_model_events`; the oldest are dropped beyond this.
+pub const MAX_MODEL_EVENTS: usize = 1_000;
+
+// Completions kept for `get_completion`, and finished completion jobs kept for
+// `get_completion_job`; the oldest are dropped beyond this.
+pub const MAX_STORED_COMPLETIONS: usize = 10_000;
+
+// Version given to models restored from state saved before models were versioned.
+pub const LEGACY_MODEL_VERSION: &str = "0.0.0";
+
+// Serialized wholesale into stable memory across upgrades (see `stable_state`); fields missing
+// from an older snapshot fall back to their defaults.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct TaskManagerImpl {
     users: HashMap<String, User>,
     model_chunks: HashMap<String, ModelChunk>,
-    models: HashMap<String, Model>,
+    models: HashMap<String, Model>, // Currently promoted version of each model
+    model_versions: HashMap<String, Vec<ModelVersion>>, // Keyed by model id, in publish order
+    #[serde(with = "crate::stable_state::blob_map")]
+    model_weights: HashMap<String, Vec<u8>>, // Full weight blob per model id, sharded on distribution
+    #[serde(with = "crate::stable_state::blob_map")]
+    version_weights: HashMap<String, Vec<u8>>, // Weights of versions not currently promoted
+    #[serde(with = "crate::stable_state::blob_map")]
+    model_tokenizers: HashMap<String, Vec<u8>>, // `tokenizer.json` per model id, used by ONNX
+    model_events: VecDeque<ModelEvent>, // Oldest first
+    next_model_event: u64,
+    shard_verifications: HashMap<String, ShardVerification>, // Keyed by `ModelChunk::shard_id`
+    verification_config: VerificationConfig,
+    pledges: HashMap<String, HashMap<String, u64>>, // User id -> model id -> pledged resources
+    reputations: HashMap<String, Reputation>, // Keyed by user id; missing means a fresh record
+    rate_limit_config: RateLimitConfig,
     training_tasks: HashMap<String, TrainingTask>,
+    training_rounds: BTreeMap<u64, TrainingRound>, // Keyed by sequence number, oldest first
+    next_training_round_id: u64,
+    uploads: BTreeMap<u64, Upload>, // Multipart uploads not yet committed, by sequence number
+    next_upload_id: u64,
+    completions: BTreeMap<u64, Completion>, // Keyed by sequence number, oldest first
+    next_completion_id: u64,
+    completion_jobs: BTreeMap<u64, CompletionJob>, // Keyed by sequence number, oldest first
+    next_completion_job_id: u64,
+    api_keys: HashMap<String, String>, // Hex SHA-256 of an API key -> user id it acts as
+    controllers: HashSet<Principal>, // Principals allowed to manage the role sets
+    admins: HashSet<Principal>,      // Principals allowed to manage models and training tasks
+    #[serde(skip)]
+    text_generators: HashMap<String, Box<dyn TextGenerator>>, // Loaded backends, never persisted
 }
 
-#[derive(Clone, Deserialize, Serialize, CandidType)]
-pub struct Model {
-    pub id: String,
-    pub min_resources: u64,
-    pub active: bool,
-}
+impl TaskManagerInterface for TaskManagerImpl {
+    fn init(&mut self, controller: Principal) {
+        self.controllers.insert(controller);
+    }
+
+    fn check_controller_access(&self, caller: &Principal) -> Result<(), TaskManagerError> {
+        if self.controllers.contains(caller) {
+            Ok(())
+        } else {
+            Err(TaskManagerError::UnauthorizedAccess {
+                required_role: Role::Controller,
+            })
+        }
+    }
 
-impl Default for TaskManagerImpl {
-    fn default() -> Self {
-        Self {
-            users: HashMap::new(),
-            model_chunks: HashMap::new(),
-            models: HashMap::new(),
-            training_tasks: HashMap::new(),
+    fn check_admin_access(&self, caller: &Principal) -> Result<(), TaskManagerError> {
+        // Controllers implicitly hold every admin permission.
+        if self.admins.contains(caller) || self.controllers.contains(caller) {
+            Ok(())
+        } else {
+            Err(TaskManagerError::UnauthorizedAccess {
+                required_role: Role::Admin,
+            })
         }
     }
-}
 
-impl TaskManagerInterface for TaskManagerImpl {
-    fn init(&mut self) {}
+    fn check_user_access(&self, caller: &Principal, user_id: &str) -> Result<(), TaskManagerError> {
+        let user = self.get_user_ref(user_id)?;
+        if user.owner.as_ref() == Some(caller) {
+            Ok(())
+        } else {
+            Err(TaskManagerError::NotUserOwner {
+                user_id: user_id.to_string(),
+            })
+        }
+    }
+
+    fn check_registered_user(&self, caller: &Principal) -> Result<(), TaskManagerError> {
+        if self.users.values().any(|user| user.owner.as_ref() == Some(caller)) {
+            Ok(())
+        } else {
+            Err(TaskManagerError::NotRegisteredUser)
+        }
+    }
+
+    fn add_controller(&mut self, principal: Principal) -> Result<(), TaskManagerError> {
+        if !self.controllers.insert(principal) {
+            return Err(TaskManagerError::RoleAlreadyAssigned {
+                role: Role::Controller,
+            });
+        }
+        Ok(())
+    }
+
+    fn remove_controller(&mut self, principal: &Principal) -> Result<(), TaskManagerError> {
+        if !self.controllers.contains(principal) {
+            return Err(TaskManagerError::RoleNotAssigned {
+                role: Role::Controller,
+            });
+        }
+        if self.controllers.len() == 1 {
+            return Err(TaskManagerError::LastController);
+        }
+        self.controllers.remove(principal);
+        Ok(())
+    }
+
+    fn add_admin(&mut self, principal: Principal) -> Result<(), TaskManagerError> {
+        if !self.admins.insert(principal) {
+            return Err(TaskManagerError::RoleAlreadyAssigned { role: Role::Admin });
+        }
+        Ok(())
+    }
+
+    fn remove_admin(&mut self, principal: &Principal) -> Result<(), TaskManagerError> {
+        if !self.admins.remove(principal) {
+            return Err(TaskManagerError::RoleNotAssigned { role: Role::Admin });
+        }
+        Ok(())
+    }
+
+    fn get_controllers(&self) -> Vec<Principal> {
+        self.controllers.iter().cloned().collect()
+    }
 
-    fn register_user(&mut self, user: User) -> Result<String, String> {
+    fn get_admins(&self) -> Vec<Principal> {
+        self.admins.iter().cloned().collect()
+    }
+
+    fn find_user_id(&self, caller: &Principal) -> Result<String, TaskManagerError> {
+        self.users
+            .values()
+            .find(|user| user.owner.as_ref() == Some(caller))
+            .map(|user| user.id.clone())
+            .ok_or(TaskManagerError::NotRegisteredUser)
+    }
+
+    fn consume_rate_limit_token(
+        &mut self,
+        user_id: &str,
+        now: u64,
+    ) -> Result<u64, TaskManagerError> {
+        let user = self
+            .users
+            .get_mut(user_id)
+            .ok_or_else(|| TaskManagerError::UserNotFound {
+                user_id: user_id.to_string(),
+            })?;
+        let limits = self.rate_limit_config.limits(user.tier);
+        try_consume(user, limits, now)
+            .map_err(|retry_after_ns| TaskManagerError::RateLimited { retry_after_ns })
+    }
+
+    fn set_user_tier(
+        &mut self,
+        user_id: &str,
+        tier: RateLimitTier,
+    ) -> Result<(), TaskManagerError> {
+        self.get_user_mut(user_id)?.tier = tier;
+        Ok(())
+    }
+
+    fn set_rate_limit_config(&mut self, config: RateLimitConfig) -> Result<(), TaskManagerError> {
+        config.validate()?;
+        self.rate_limit_config = config;
+        Ok(())
+    }
+
+    fn get_rate_limit_config(&self) -> RateLimitConfig {
+        self.rate_limit_config.clone()
+    }
+
+    fn register_user(&mut self, mut user: User) -> Result<String, TaskManagerError> {
         if self.users.contains_key(&user.id) {
-            return Err("User already exists.".to_string());
+            return Err(TaskManagerError::UserAlreadyExists {
+                user_id: user.id.clone(),
+            });
+        }
+        if let Some(owner) = &user.owner {
+            if let Ok(existing) = self.find_user_id(owner) {
+                return Err(TaskManagerError::PrincipalAlreadyOwnsUser { user_id: existing });
+            }
         }
+        // Balances are earned rather than declared: whatever the caller sent, a new user starts
+        // with no rewards, a full token bucket and a fresh reputation.
+        user.rewards = 0;
+        user.rate_limit_tokens = self.rate_limit_config.limits(user.tier).capacity;
+        user.rate_limit_refilled_at = 0;
+        self.reputations.remove(&user.id);
         let user_id = user.id.clone();
         self.users.insert(user.id.clone(), user);
         Ok(user_id)
     }
 
-    fn update_user_resources(&mut self, id: &str, resources: u64) -> Result<(), String> {
-        if let Some(user) = self.users.get_mut(id) {
-            user.resources = resources;
-            Ok(())
+    fn update_user_resources(&mut self, id: &str, resources: u64) -> Result<(), TaskManagerError> {
+        self.get_user_ref(id)?;
+        // Pledges have to be withdrawn before the resources backing them.
+        let pledged = self.pledged_by(id, None);
+        if pledged > resources {
+            return Err(TaskManagerError::PledgeExceedsResources {
+                user_id: id.to_string(),
+                pledged,
+                resources,
+            });
+        }
+        self.get_user_mut(id)?.resources = resources;
+        Ok(())
+    }
+
+    fn pledge_resources(
+        &mut self,
+        user_id: &str,
+        model_id: &str,
+        amount: u64,
+    ) -> Result<(), TaskManagerError> {
+        let resources = self.get_user_ref(user_id)?.resources;
+        self.get_model_ref(model_id)?;
+        let pledged = self.pledged_by(user_id, Some(model_id)).saturating_add(amount);
+        if pledged > resources {
+            return Err(TaskManagerError::PledgeExceedsResources {
+                user_id: user_id.to_string(),
+                pledged,
+                resources,
+            });
+        }
+        let pledges = self.pledges.entry(user_id.to_string()).or_default();
+        if amount == 0 {
+            pledges.remove(model_id);
         } else {
-            Err("User not found.".to_string())
+            pledges.insert(model_id.to_string(), amount);
+        }
+        if pledges.is_empty() {
+            self.pledges.remove(user_id);
         }
+        Ok(())
     }
 
-    fn distribute_model_chunks(&mut self) -> Result<(), String> {
-        let chunk_id = "chunk1";
-        let user_id = "user1";
-        let data = vec![0u8; 1024];
-        let chunk = ModelChunk {
-            id: chunk_id.to_string(),
-            user_id: user_id.to_string(),
-            data: data,
-        };
-        self.model_chunks.insert(chunk_id.to_string(), chunk);
+    fn get_user_pledges(&self, user_id: &str) -> Result<Vec<ResourcePledge>, TaskManagerError> {
+        self.get_user_ref(user_id)?;
+        let mut pledges: Vec<ResourcePledge> = self
+            .pledges
+            .get(user_id)
+            .into_iter()
+            .flatten()
+            .map(|(model_id, &amount)| ResourcePledge {
+                model_id: model_id.clone(),
+                amount,
+            })
+            .collect();
+        pledges.sort_by(|a, b| a.model_id.cmp(&b.model_id));
+        Ok(pledges)
+    }
+
+    fn get_model_capacity(&self, model_id: &str) -> Result<u64, TaskManagerError> {
+        self.get_model_ref(model_id)?;
+        Ok(self.model_capacity(model_id))
+    }
+
+    fn upload_model_weights(
+        &mut self,
+        model_id: &str,
+        weights: Vec<u8>,
+    ) -> Result<(), TaskManagerError> {
+        Self::check_accepts_weights(self.get_model_ref(model_id)?)?;
+        if weights.is_empty() {
+            return Err(TaskManagerError::invalid_argument("weights", "cannot be empty"));
+        }
+        self.get_model_mut(model_id)?.weight_hash = content_hash(&weights);
+        self.model_weights.insert(model_id.to_string(), weights);
+        Ok(())
+    }
+
+    fn upload_model_tokenizer(
+        &mut self,
+        model_id: &str,
+        tokenizer: Vec<u8>,
+    ) -> Result<(), TaskManagerError> {
+        self.get_model_ref(model_id)?;
+        // Parsed now so a broken file is reported here rather than at the next load.
+        GPT2Tokenizer::from_bytes(&tokenizer)?;
+        self.model_tokenizers.insert(model_id.to_string(), tokenizer);
+        self.text_generators.remove(model_id);
+        Ok(())
+    }
+
+    fn distribute_model_chunks(
+        &mut self,
+        model_id: &str,
+        chunk_size: u64,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        self.get_model_ref(model_id)?;
+        if chunk_size == 0 {
+            return Err(TaskManagerError::invalid_argument(
+                "chunk_size",
+                "must be greater than zero",
+            ));
+        }
+        let weights = self.model_weights.get(model_id).ok_or_else(|| {
+            TaskManagerError::ModelWeightsNotFound {
+                model_id: model_id.to_string(),
+            }
+        })?;
+
+        // Clamped before the cast, which would truncate sizes of 2^32 and above on wasm32.
+        let chunk_size = usize::try_from(chunk_size)
+            .map_or(weights.len(), |size| size.min(weights.len()))
+            .max(1);
+        let shards: Vec<&[u8]> = weights.chunks(chunk_size).collect();
+        let total_chunks = shards.len() as u32;
+        let config = self.verification_config.clone();
+        let holders = Self::allocate_shards(
+            self.model_pledges(model_id),
+            &self.reputations,
+            shards.len(),
+            config.redundancy as usize,
+        )?;
+        let mut chunks = Vec::new();
+        let mut verifications = Vec::new();
+        for (index, (data, shard_holders)) in shards.into_iter().zip(holders).enumerate() {
+            for (replica, user_id) in shard_holders.into_iter().enumerate() {
+                chunks.push(ModelChunk {
+                    id: format!("{}:{}:{}", model_id, index, replica),
+                    model_id: model_id.to_string(),
+                    user_id,
+                    data: data.to_vec(),
+                    index: index as u32,
+                    total_chunks,
+                    replica: replica as u32,
+                    offset: (index * chunk_size) as u64,
+                    content_hash: content_hash(data),
+                    lease_expires_at: Some(now + CHUNK_LEASE_DURATION_NS),
+                });
+            }
+            let shard_id = format!("{}:{}", model_id, index);
+            verifications.push(ShardVerification::new(shard_id, model_id.to_string(), &config));
+        }
+
+        // Redistributing replaces the previous assignment and any results collected for it.
+        self.model_chunks.retain(|_, chunk| chunk.model_id != model_id);
+        self.shard_verifications
+            .retain(|_, verification| verification.model_id != model_id);
+        for chunk in chunks {
+            self.model_chunks.insert(chunk.id.clone(), chunk);
+        }
+        for verification in verifications {
+            self.shard_verifications
+                .insert(verification.shard_id.clone(), verification);
+        }
         Ok(())
     }
 
@@ -74,149 +402,1872 @@ impl TaskManagerInterface for TaskManagerImpl {
         &mut self,
         chunk: ModelChunk,
         computed_results: Vec<u32>, // Accept computed results as an argument
-    ) -> Result<(), String> {
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        let shard_id = self
+            .leased_chunk_mut(&chunk.id, &chunk.user_id, now)?
+            .shard_id();
+
+        let verification = self.shard_verifications.get_mut(&shard_id).ok_or_else(|| {
+            TaskManagerError::ShardVerificationNotFound {
+                shard_id: shard_id.clone(),
+            }
+        })?;
+        let outcome = verification.record(&chunk.user_id, computed_results)?;
         if let Some(existing_chunk) = self.model_chunks.get_mut(&chunk.id) {
-            existing_chunk.data = chunk.data;
-        } else {
-            return Err("Model chunk not found.".to_string());
+            existing_chunk.lease_expires_at = None;
         }
 
-        // Use the computed results directly instead of running the GPU computation
-        let reward = self.calculate_rewards(&chunk.user_id, 1);
-        println!("User {} earned {} tokens.", chunk.user_id, reward);
+        // Only results that agree with the majority answer earn a reward; results proven wrong
+        // cost the submitter part of what they have accrued.
+        for user_id in outcome.accepted {
+            self.reputations.entry(user_id.clone()).or_default().record_accepted();
+            self.calculate_rewards(&user_id, 1);
+        }
+        for user_id in outcome.rejected {
+            self.reputations.entry(user_id.clone()).or_default().record_rejected();
+            self.slash_rewards(&user_id);
+        }
+
+        Ok(())
+    }
+
+    fn get_reputation(&self, user_id: &str) -> Result<Reputation, TaskManagerError> {
+        self.get_user_ref(user_id)?;
+        Ok(self.reputations.get(user_id).cloned().unwrap_or_default())
+    }
+
+    fn get_shard_verification(
+        &self,
+        shard_id: &str,
+    ) -> Result<ShardVerification, TaskManagerError> {
+        self.shard_verifications
+            .get(shard_id)
+            .cloned()
+            .ok_or_else(|| TaskManagerError::ShardVerificationNotFound {
+                shard_id: shard_id.to_string(),
+            })
+    }
 
+    fn set_verification_config(
+        &mut self,
+        config: VerificationConfig,
+    ) -> Result<(), TaskManagerError> {
+        config.validate()?;
+        self.verification_config = config;
         Ok(())
     }
 
-    fn get_model_chunks(&self, user_id: &str) -> Result<Vec<ModelChunk>, String> {
+    fn get_verification_config(&self) -> VerificationConfig {
+        self.verification_config.clone()
+    }
+
+    fn renew_chunk_lease(
+        &mut self,
+        user_id: &str,
+        chunk_id: &str,
+        now: u64,
+    ) -> Result<u64, TaskManagerError> {
+        let chunk = self.leased_chunk_mut(chunk_id, user_id, now)?;
+        let expires_at = now + CHUNK_LEASE_DURATION_NS;
+        chunk.lease_expires_at = Some(expires_at);
+        Ok(expires_at)
+    }
+
+    fn reclaim_expired_leases(&mut self, now: u64) -> Vec<String> {
+        let mut expired: Vec<String> = self
+            .model_chunks
+            .values()
+            .filter(|chunk| match chunk.lease_expires_at {
+                Some(expires_at) => expires_at <= now || chunk.user_id.is_empty(),
+                None => false,
+            })
+            .map(|chunk| chunk.id.clone())
+            .collect();
+        expired.sort();
+
+        let mut reassigned = Vec::new();
+        for chunk_id in expired {
+            let shard_id = self.model_chunks[&chunk_id].shard_id();
+            let decided = matches!(
+                self.shard_verifications.get(&shard_id),
+                Some(verification) if verification.status != VerificationStatus::Pending
+            );
+            if decided {
+                // The shard no longer needs this replica's result.
+                self.model_chunks.get_mut(&chunk_id).unwrap().lease_expires_at = None;
+                continue;
+            }
+            // Replicas must stay with independent users, so skip everyone already on this shard.
+            let exclude: Vec<String> = self
+                .model_chunks
+                .values()
+                .filter(|chunk| chunk.shard_id() == shard_id)
+                .map(|chunk| chunk.user_id.clone())
+                .collect();
+            let previous_holder = self.model_chunks[&chunk_id].user_id.clone();
+            if !previous_holder.is_empty() {
+                self.reputations.entry(previous_holder).or_default().record_timed_out();
+            }
+            let model_id = self.model_chunks[&chunk_id].model_id.clone();
+            let target = self.pick_lease_target(&model_id, &exclude);
+            let chunk = self.model_chunks.get_mut(&chunk_id).unwrap();
+            // With nobody else available the chunk is left unassigned and retried next pass.
+            chunk.user_id = target.clone().unwrap_or_default();
+            chunk.lease_expires_at = Some(now + CHUNK_LEASE_DURATION_NS);
+            if target.is_some() {
+                reassigned.push(chunk_id);
+            }
+        }
+        reassigned
+    }
+
+    fn get_model_chunks(&self, user_id: &str) -> Result<Vec<ModelChunk>, TaskManagerError> {
         let mut chunks = Vec::new();
         for chunk in self.model_chunks.values() {
             if chunk.user_id == user_id {
                 chunks.push(chunk.clone());
             }
         }
+        chunks.sort_by(|a, b| a.model_id.cmp(&b.model_id).then(a.index.cmp(&b.index)));
         Ok(chunks)
     }
 
-    fn get_rewards(&self, user_id: &str) -> Result<u64, String> {
-        if let Some(user) = self.users.get(user_id) {
-            Ok(user.rewards)
-        } else {
-            Err("User not found.".to_string())
-        }
+    fn get_reassembled_model(&self, model_id: &str) -> Result<Vec<u8>, TaskManagerError> {
+        self.get_model_ref(model_id)?;
+        let chunks: Vec<&ModelChunk> = self
+            .model_chunks
+            .values()
+            .filter(|chunk| chunk.model_id == model_id && chunk.replica == 0)
+            .collect();
+        reassemble_chunks(model_id, &chunks)
+    }
+
+    fn reassemble_model(&mut self, model_id: &str) -> Result<Vec<u8>, TaskManagerError> {
+        let weights = self.get_reassembled_model(model_id)?;
+        let hash = content_hash(&weights);
+        self.get_model_mut(model_id)?.weight_hash = hash.clone();
+        self.model_weights.insert(model_id.to_string(), weights);
+        Ok(hash)
+    }
+
+    fn get_rewards(&self, user_id: &str) -> Result<u64, TaskManagerError> {
+        Ok(self.get_user_ref(user_id)?.rewards)
     }
 
-    fn register_model(&mut self, model: Model) -> Result<String, String> {
-        if self.models.contains_key(&model.id) {
-            return Err("Model already exists.".to_string());
+    fn add_api_key(&mut self, user_id: &str, key: &str) -> Result<(), TaskManagerError> {
+        self.get_user_ref(user_id)?;
+        if key.len() < 32 {
+            return Err(TaskManagerError::invalid_argument("key", "is too short"));
         }
+        self.api_keys.insert(Self::api_key_hash(key), user_id.to_string());
+        Ok(())
+    }
+
+    fn revoke_api_keys(&mut self, user_id: &str) -> Result<u32, TaskManagerError> {
+        self.get_user_ref(user_id)?;
+        let before = self.api_keys.len();
+        self.api_keys.retain(|_, owner| owner != user_id);
+        Ok((before - self.api_keys.len()) as u32)
+    }
+
+    fn find_api_key_user(&self, key: &str) -> Result<String, TaskManagerError> {
+        self.api_keys
+            .get(&Self::api_key_hash(key))
+            .cloned()
+            .ok_or(TaskManagerError::InvalidApiKey)
+    }
+
+    fn register_model(&mut self, mut model: Model, now: u64) -> Result<String, TaskManagerError> {
+        model.validate()?;
+        // The hash always describes weights the canister actually holds.
+        model.weight_hash = Vec::new();
+        // Models go live through `activate_model`, which checks the pledged capacity.
+        model.active = false;
+        model.degraded = false;
         let model_id = model.id.clone();
-        self.models.insert(model_id.clone(), model);
+        model.created_at = self.models.get(&model_id).map_or(now, |current| current.created_at);
+        let versions = self.model_versions.entry(model_id.clone()).or_default();
+        if versions.iter().any(|published| published.model.version == model.version)
+            || self.models.get(&model_id).map(|current| &current.version) == Some(&model.version)
+        {
+            return Err(TaskManagerError::ModelVersionAlreadyExists {
+                model_id,
+                version: model.version,
+            });
+        }
+        versions.push(ModelVersion {
+            model: model.clone(),
+            published_at: now,
+        });
+        // Later versions are only published; they go live through `promote_model_version`.
+        self.models.entry(model_id.clone()).or_insert(model);
 
         Ok(model_id)
     }
 
-    fn activate_model(&mut self, model_id: &str) -> Result<(), String> {
-        let total_resources: u64 = self.users.values().map(|user| user.resources).sum();
-        let model = self.models.get_mut(model_id).ok_or("Model not found.")?;
+    fn upload_model_version_weights(
+        &mut self,
+        model_id: &str,
+        version: &str,
+        weights: Vec<u8>,
+    ) -> Result<(), TaskManagerError> {
+        if self.get_model_ref(model_id)?.version == version {
+            return self.upload_model_weights(model_id, weights);
+        }
+        let published = self.get_model_version_mut(model_id, version)?;
+        Self::check_accepts_weights(&published.model)?;
+        if weights.is_empty() {
+            return Err(TaskManagerError::invalid_argument("weights", "cannot be empty"));
+        }
+        published.model.weight_hash = content_hash(&weights);
+        self.version_weights
+            .insert(Self::version_key(model_id, version), weights);
+        Ok(())
+    }
+
+    fn get_model_versions(&self, model_id: &str) -> Result<Vec<ModelVersion>, TaskManagerError> {
+        let current = self.get_model_ref(model_id)?;
+        let mut versions = self.model_versions.get(model_id).cloned().unwrap_or_default();
+        // The promoted version's record is refreshed from the live registry entry.
+        for published in versions.iter_mut() {
+            if published.model.version == current.version {
+                published.model = current.clone();
+            }
+        }
+        Ok(versions)
+    }
+
+    fn promote_model_version(
+        &mut self,
+        model_id: &str,
+        version: &str,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        let current = self.get_model_ref(model_id)?.clone();
+        let target = self.get_model_version_mut(model_id, version)?.model.clone();
+        if current.version == version {
+            return Ok(());
+        }
+        let target_key = Self::version_key(model_id, version);
+        if !self.is_promotable(model_id, &target) {
+            return Err(TaskManagerError::ModelWeightsNotFound {
+                model_id: model_id.to_string(),
+            });
+        }
+
+        // Archive the outgoing version together with its weights.
+        let current_version = current.version.clone();
+        let active = current.active;
+        self.get_model_version_mut(model_id, &current_version)?.model = current;
+        if let Some(weights) = self.model_weights.remove(model_id) {
+            self.version_weights
+                .insert(Self::version_key(model_id, &current_version), weights);
+        }
+
+        if let Some(weights) = self.version_weights.remove(&target_key) {
+            self.model_weights.insert(model_id.to_string(), weights);
+        }
+        let model = self.get_model_mut(model_id)?;
+        *model = target;
+        model.active = active;
+        // A loaded backend still runs the old weights and has to be loaded again.
+        self.text_generators.remove(model_id);
+        self.reshard_model_chunks(model_id, now);
+        Ok(())
+    }
+
+    fn rollback_model(&mut self, model_id: &str, now: u64) -> Result<String, TaskManagerError> {
+        let current = parse_version(&self.get_model_ref(model_id)?.version);
+        let previous = self
+            .model_versions
+            .get(model_id)
+            .into_iter()
+            .flatten()
+            .filter(|published| self.is_promotable(model_id, &published.model))
+            .filter_map(|published| {
+                let version = parse_version(&published.model.version)?;
+                (Some(version) < current).then_some((version, published.model.version.clone()))
+            })
+            .max()
+            .map(|(_, version)| version)
+            .ok_or_else(|| TaskManagerError::NoPreviousModelVersion {
+                model_id: model_id.to_string(),
+            })?;
+        self.promote_model_version(model_id, &previous, now)?;
+        Ok(previous)
+    }
+
+    fn get_model(&self, model_id: &str) -> Result<Model, TaskManagerError> {
+        self.get_model_ref(model_id).cloned()
+    }
+
+    fn get_models_by_architecture(
+        &self,
+        architecture: ModelArchitecture,
+        query: &ModelQuery,
+    ) -> Result<ModelPage, TaskManagerError> {
+        self.page_models(query, |model| model.architecture == architecture)
+    }
+
+    fn activate_model(&mut self, model_id: &str) -> Result<(), TaskManagerError> {
+        let total_resources = self.model_capacity(model_id);
+        let model = self.get_model_mut(model_id)?;
         if total_resources >= model.min_resources {
             model.active = true;
             Ok(())
         } else {
-            Err("Insufficient resources to activate the model.".to_string())
+            Err(TaskManagerError::InsufficientResources {
+                model_id: model_id.to_string(),
+                required: model.min_resources,
+                available: total_resources,
+            })
         }
     }
 
-    fn deactivate_model(&mut self, model_id: &str) -> Result<(), String> {
-        let model = self.models.get_mut(model_id).ok_or("Model not found.")?;
-        model.active = false;
+    fn deactivate_model(&mut self, model_id: &str) -> Result<(), TaskManagerError> {
+        self.get_model_mut(model_id)?.active = false;
         Ok(())
     }
 
-    fn get_active_models(&self, offset: usize, limit: usize) -> Vec<Model> {
-        self.models
-            .values()
-            .filter(|model| model.active)
-            .skip(offset)
+    fn set_model_capacity_policy(
+        &mut self,
+        model_id: &str,
+        policy: CapacityPolicy,
+    ) -> Result<(), TaskManagerError> {
+        let model = self.get_model_mut(model_id)?;
+        model.capacity_policy = policy;
+        if policy != CapacityPolicy::Degrade {
+            model.degraded = false;
+        }
+        Ok(())
+    }
+
+    fn reconcile_model_capacity(&mut self, now: u64) -> Vec<ModelEvent> {
+        let mut model_ids: Vec<String> = self.models.keys().cloned().collect();
+        model_ids.sort();
+
+        let mut events = Vec::new();
+        for model_id in model_ids {
+            let capacity = self.model_capacity(&model_id);
+            let model = self.models.get_mut(&model_id).unwrap();
+            let sufficient = capacity >= model.min_resources;
+            let kind = match (model.capacity_policy, sufficient) {
+                (CapacityPolicy::Manual, _) => None,
+                (_, true) if !model.active => {
+                    model.active = true;
+                    model.degraded = false;
+                    Some(ModelEventKind::Activated)
+                }
+                (_, true) if model.degraded => {
+                    model.degraded = false;
+                    Some(ModelEventKind::Recovered)
+                }
+                (CapacityPolicy::Deactivate, false) if model.active => {
+                    model.active = false;
+                    Some(ModelEventKind::Deactivated)
+                }
+                (CapacityPolicy::Degrade, false) if model.active && !model.degraded => {
+                    model.degraded = true;
+                    Some(ModelEventKind::Degraded)
+                }
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                let min_resources = model.min_resources;
+                events.push(self.record_model_event(model_id, kind, capacity, min_resources, now));
+            }
+        }
+        events
+    }
+
+    fn get_model_events(&self, after: Option<u64>, limit: usize) -> Vec<ModelEvent> {
+        self.model_events
+            .iter()
+            .filter(|event| after.is_none_or(|after| event.sequence > after))
             .take(limit)
             .cloned()
             .collect()
     }
 
-    fn get_models_needing_resources(&self, offset: usize, limit: usize) -> Vec<Model> {
-        let total_resources: u64 = self.users.values().map(|user| user.resources).sum();
-        self.models
-            .values()
-            .filter(|model| !model.active && model.min_resources > total_resources)
-            .skip(offset)
-            .take(limit)
+    fn get_active_models(&self, query: &ModelQuery) -> Result<ModelPage, TaskManagerError> {
+        self.page_models(query, |model| model.active)
+    }
+
+    fn get_models_needing_resources(
+        &self,
+        query: &ModelQuery,
+    ) -> Result<ModelPage, TaskManagerError> {
+        self.page_models(query, |model| {
+            !model.active && model.min_resources > self.model_capacity(&model.id)
+        })
+    }
+
+    fn load_inference_backend(&mut self, model_id: &str) -> Result<(), TaskManagerError> {
+        let backend = self.get_model_ref(model_id)?.backend;
+        let weights = self.model_weights.get(model_id).map(Vec::as_slice);
+        let tokenizer = self.model_tokenizers.get(model_id).map(Vec::as_slice);
+        let generator = load_text_generator(backend, model_id, weights, tokenizer)?;
+        self.set_text_generator(model_id, generator);
+        Ok(())
+    }
+
+    fn generate_completion(
+        &mut self,
+        user_id: &str,
+        request: &CompletionRequest,
+        now: u64,
+    ) -> Result<Completion, TaskManagerError> {
+        request.validate()?;
+        let model_id = self.resolve_completion_model(request)?;
+        let generator = self.text_generators.get(&model_id).ok_or_else(|| {
+            TaskManagerError::InferenceBackendNotLoaded {
+                model_id: model_id.clone(),
+            }
+        })?;
+        let generation = generator.generate(request)?;
+
+        Ok(self.store_completion(Completion {
+            id: String::new(),
+            model_id,
+            user_id: user_id.to_string(),
+            prompt: request.prompt.clone(),
+            generated_text: generation.text,
+            prompt_tokens: generation.prompt_tokens,
+            completion_tokens: generation.completion_tokens,
+            token_logprobs: generation.token_logprobs,
+            finish_reason: generation.finish_reason,
+            created_at: now,
+        }))
+    }
+
+    fn get_completion(&self, completion_id: &str) -> Result<Completion, TaskManagerError> {
+        completion_id
+            .strip_prefix("cmpl-")
+            .and_then(|sequence| sequence.parse::<u64>().ok())
+            .and_then(|sequence| self.completions.get(&sequence))
             .cloned()
-            .collect()
+            .ok_or_else(|| TaskManagerError::CompletionNotFound {
+                completion_id: completion_id.to_string(),
+            })
     }
 
-    fn generate_completion(&self, prompt: &str) -> Result<Completion, String> {
-        let active_models = self.get_active_models(0, 1);
-        if active_models.is_empty() {
-            return Err("No active models available.".to_string());
+    fn submit_completion_job(
+        &mut self,
+        user_id: &str,
+        request: CompletionRequest,
+        now: u64,
+    ) -> Result<String, TaskManagerError> {
+        request.validate()?;
+        let model_id = self.resolve_completion_model(&request)?;
+        self.expire_completion_jobs(now);
+        let pending = self.completion_jobs.values().filter(|job| !job.is_finished()).count();
+        if pending >= MAX_PENDING_COMPLETION_JOBS {
+            return Err(TaskManagerError::CompletionQueueFull);
         }
-        let model = &active_models[0];
-        let generated_text = format!("Generated text for '{}' using model '{}'", prompt, model.id);
-        Ok(Completion {
-            prompt: prompt.to_string(),
-            generated_text,
-        })
+        let sequence = self.next_completion_job_id;
+        self.next_completion_job_id += 1;
+        let job_id = format!("job-{}", sequence);
+        self.completion_jobs.insert(
+            sequence,
+            CompletionJob {
+                id: job_id.clone(),
+                model_id,
+                user_id: user_id.to_string(),
+                request,
+                status: CompletionJobStatus::Queued,
+                worker_id: None,
+                lease_expires_at: None,
+                output: String::new(),
+                completion_tokens: 0,
+                token_logprobs: Vec::new(),
+                completion_id: None,
+                error: None,
+                verified: None,
+                created_at: now,
+                updated_at: now,
+            },
+        );
+        self.evict_completion_jobs();
+        Ok(job_id)
     }
 
-    // fn generate_completion(&self, prompt: &str) -> Result<Completion, String> {
-    //     // Load the GPT-2 tokenizer and model.
-    //     let tokenizer = Gpt2Tokenizer::from_pretrained("gpt2", None)?;
-    //     let config = Gpt2Config::from_pretrained("gpt2");
-    //     let model = Gpt2ForGeneration::from_pretrained("gpt2", Some(config))?;
+    fn claim_completion_job(
+        &mut self,
+        worker_id: &str,
+        now: u64,
+    ) -> Result<Option<CompletionJob>, TaskManagerError> {
+        self.get_user_ref(worker_id)?;
+        self.expire_completion_jobs(now);
+        // Workers only serve models they hold at least one shard of, and never their own jobs.
+        let held_models: HashSet<&str> = self
+            .model_chunks
+            .values()
+            .filter(|chunk| chunk.user_id == worker_id)
+            .map(|chunk| chunk.model_id.as_str())
+            .collect();
+        let job = self
+            .completion_jobs
+            .values_mut()
+            .filter(|job| job.user_id != worker_id)
+            .find(|job| job.is_claimable(now) && held_models.contains(job.model_id.as_str()));
+        Ok(job.map(|job| {
+            // Output from a worker whose lease lapsed is discarded; the new worker starts over.
+            job.status = CompletionJobStatus::Running;
+            job.worker_id = Some(worker_id.to_string());
+            job.lease_expires_at = Some(now + COMPLETION_JOB_LEASE_DURATION_NS);
+            job.output.clear();
+            job.completion_tokens = 0;
+            job.token_logprobs.clear();
+            job.updated_at = now;
+            job.clone()
+        }))
+    }
 
-    //     // Create a language generator.
-    //     let generate_config = GenerateConfig {
-    //         max_length: 100,
-    //         ..Default::default()
-    //     };
-    //     let language_generator = LanguageGenerator::new(model, tokenizer, generate_config);
+    fn post_completion_job_update(
+        &mut self,
+        worker_id: &str,
+        job_id: &str,
+        update: CompletionJobUpdate,
+        now: u64,
+    ) -> Result<CompletionJobStatus, TaskManagerError> {
+        let job = self.get_completion_job_mut(job_id)?;
+        if job.status != CompletionJobStatus::Running || job.worker_id.as_deref() != Some(worker_id)
+        {
+            return Err(TaskManagerError::CompletionJobNotAssigned {
+                job_id: job_id.to_string(),
+                user_id: worker_id.to_string(),
+            });
+        }
+        let completion_tokens = job.completion_tokens.saturating_add(update.tokens);
+        if completion_tokens > job.request.max_new_tokens {
+            return Err(TaskManagerError::invalid_argument(
+                "tokens",
+                "exceeds the request's max_new_tokens",
+            ));
+        }
+        if update.tokens == 0 && !update.text.is_empty() {
+            return Err(TaskManagerError::invalid_argument(
+                "text",
+                "must be posted together with the tokens it was generated from",
+            ));
+        }
+        let output_len = job.output.len().saturating_add(update.text.len());
+        if output_len > completion_tokens as usize * MAX_COMPLETION_BYTES_PER_TOKEN {
+            return Err(TaskManagerError::invalid_argument(
+                "text",
+                &format!(
+                    "exceeds {} bytes per generated token",
+                    MAX_COMPLETION_BYTES_PER_TOKEN
+                ),
+            ));
+        }
+        if job.request.logprobs && update.token_logprobs.len() != update.tokens as usize {
+            return Err(TaskManagerError::invalid_argument(
+                "token_logprobs",
+                "must hold one entry per generated token",
+            ));
+        }
 
-    //     // Generate text based on the input prompt.
-    //     let input_context = prompt.to_string();
-    //     let generated_text = language_generator.generate(Some(&[input_context]), None)?;
+        job.output.push_str(&update.text);
+        job.completion_tokens = completion_tokens;
+        if job.request.logprobs {
+            job.token_logprobs.extend(update.token_logprobs);
+        }
+        job.lease_expires_at = Some(now + COMPLETION_JOB_LEASE_DURATION_NS);
+        job.updated_at = now;
 
-    //     // Extract the generated text.
-    //     let generated_text = generated_text.first().ok_or("No text generated")?.text.clone();
+        if let Some(error) = update.error {
+            job.status = CompletionJobStatus::Failed;
+            job.error = Some(error);
+            job.lease_expires_at = None;
+        } else if let Some(mut finish_reason) = update.finish_reason {
+            // Workers are not trusted to honour stop sequences, so the output is cut here too.
+            if let Some(stop) = find_stop(&job.output, &job.request.stop_sequences) {
+                job.output.truncate(stop);
+                finish_reason = FinishReason::StopSequence;
+            }
+            let completion = Completion {
+                id: String::new(),
+                model_id: job.model_id.clone(),
+                user_id: job.user_id.clone(),
+                prompt: job.request.prompt.clone(),
+                generated_text: job.output.clone(),
+                prompt_tokens: update.prompt_tokens,
+                completion_tokens: job.completion_tokens,
+                token_logprobs: job
+                    .request
+                    .logprobs
+                    .then(|| job.token_logprobs.clone()),
+                finish_reason,
+                created_at: now,
+            };
+            let completion_id = self.store_completion(completion).id;
+            let job = self.get_completion_job_mut(job_id)?;
+            job.status = CompletionJobStatus::Completed;
+            job.completion_id = Some(completion_id);
+            job.lease_expires_at = None;
+        }
 
-    //     Ok(Completion {
-    //         prompt: prompt.to_string(),
-    //         generated_text,
-    //     })
-    // }
+        let status = self.get_completion_job_mut(job_id)?.status;
+        self.evict_completion_jobs();
+        Ok(status)
+    }
 
-    fn create_training_task(&mut self, task: TrainingTask) -> Result<String, String> {
+    // Output is only paid for once an admin accepts it; rejected output counts against the
+    // worker like a wrong shard result.
+    fn review_completion_job(
+        &mut self,
+        job_id: &str,
+        accepted: bool,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        let job = self.get_completion_job_mut(job_id)?;
+        if job.status != CompletionJobStatus::Completed || job.verified.is_some() {
+            return Err(TaskManagerError::invalid_argument(
+                "job_id",
+                "only completed jobs that have not been reviewed can be reviewed",
+            ));
+        }
+        job.verified = Some(accepted);
+        job.updated_at = now;
+        let worker_id = job.worker_id.clone().unwrap_or_default();
+        let reputation = self.reputations.entry(worker_id.clone()).or_default();
+        if accepted {
+            reputation.record_accepted();
+            self.calculate_rewards(&worker_id, 1);
+        } else {
+            reputation.record_rejected();
+            self.slash_rewards(&worker_id);
+        }
+        Ok(())
+    }
+
+    fn get_completion_job(&self, job_id: &str) -> Result<CompletionJob, TaskManagerError> {
+        Self::completion_job_sequence(job_id)
+            .and_then(|sequence| self.completion_jobs.get(&sequence))
+            .cloned()
+            .ok_or_else(|| TaskManagerError::CompletionJobNotFound {
+                job_id: job_id.to_string(),
+            })
+    }
+
+    fn create_training_task(
+        &mut self,
+        mut task: TrainingTask,
+        now: u64,
+    ) -> Result<String, TaskManagerError> {
         if self.training_tasks.contains_key(&task.id) {
-            return Err("Training task already exists.".to_string());
+            return Err(TaskManagerError::TrainingTaskAlreadyExists {
+                task_id: task.id.clone(),
+            });
         }
+        task.config.validate()?;
+        // Lifecycle fields are the canister's to maintain, whatever the caller sent.
+        task.round_id = None;
+        task.num_samples = 0;
+        task.status = TrainingTaskStatus::Pending;
+        task.assignee = None;
+        task.error = None;
+        task.created_at = now;
+        task.assigned_at = None;
+        task.submitted_at = None;
+        task.finished_at = None;
+        task.updated_at = now;
         let task_id = task.id.clone();
         self.training_tasks.insert(task_id.clone(), task);
         Ok(task_id)
     }
 
+    fn get_training_task(&self, task_id: &str) -> Result<TrainingTask, TaskManagerError> {
+        self.training_tasks
+            .get(task_id)
+            .cloned()
+            .ok_or_else(|| TaskManagerError::TrainingTaskNotFound {
+                task_id: task_id.to_string(),
+            })
+    }
+
+    fn claim_training_task(&mut self, user_id: &str, now: u64) -> Option<TrainingTask> {
+        // A round only gains from its participants being distinct users.
+        let joined_rounds: HashSet<&String> = self
+            .training_tasks
+            .values()
+            .filter(|task| task.assignee.as_deref() == Some(user_id))
+            .filter_map(|task| task.round_id.as_ref())
+            .collect();
+        let task_id = self
+            .training_tasks
+            .values()
+            .filter(|task| task.status == TrainingTaskStatus::Pending)
+            .filter(|task| !task.round_id.as_ref().is_some_and(|id| joined_rounds.contains(id)))
+            .min_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)))?
+            .id
+            .clone();
+        let task = self
+            .transition_training_task(&task_id, None, TrainingTaskStatus::Assigned, now)
+            .ok()?;
+        task.assignee = Some(user_id.to_string());
+        task.assigned_at = Some(now);
+        Some(task.clone())
+    }
+
+    fn start_training_task(
+        &mut self,
+        user_id: &str,
+        task_id: &str,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        self.transition_training_task(task_id, Some(user_id), TrainingTaskStatus::Running, now)?;
+        Ok(())
+    }
+
     fn submit_training_results(
         &mut self,
+        user_id: &str,
         task_id: &str,
         model_weights: Vec<u8>,
-    ) -> Result<(), String> {
-        if let Some(task) = self.training_tasks.get_mut(task_id) {
-            task.model_weights = Some(model_weights);
-            Ok(())
+        num_samples: u64,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        if model_weights.is_empty() {
+            return Err(TaskManagerError::invalid_argument("model_weights", "cannot be empty"));
+        }
+        if num_samples > MAX_NUM_SAMPLES {
+            return Err(TaskManagerError::invalid_argument(
+                "num_samples",
+                &format!("must be at most {}", MAX_NUM_SAMPLES),
+            ));
+        }
+        // Round deltas are checked now rather than failing the whole round at aggregation.
+        if let Some(round_id) = self.get_training_task(task_id)?.round_id {
+            let round = self.get_training_round(&round_id)?;
+            let base = self.model_version_weights(&round.model_id, &round.base_version)?;
+            if model_weights.len() != base.len() {
+                return Err(TaskManagerError::invalid_argument(
+                    "model_weights",
+                    "delta does not match the size of the base weights",
+                ));
+            }
+            // Infinities and NaNs would survive clipping and poison every aggregator.
+            if !decode_weights(&model_weights)?.iter().all(|value| value.is_finite()) {
+                return Err(TaskManagerError::invalid_argument(
+                    "model_weights",
+                    "delta must only contain finite values",
+                ));
+            }
+            if num_samples == 0 {
+                return Err(TaskManagerError::invalid_argument(
+                    "num_samples",
+                    "must be greater than zero",
+                ));
+            }
+        }
+        let task = self.transition_training_task(
+            task_id,
+            Some(user_id),
+            TrainingTaskStatus::Submitted,
+            now,
+        )?;
+        task.results_hash = content_hash(&model_weights);
+        task.model_weights = Some(model_weights);
+        task.num_samples = num_samples;
+        task.submitted_at = Some(now);
+        Ok(())
+    }
+
+    fn fail_training_task(
+        &mut self,
+        user_id: &str,
+        task_id: &str,
+        error: String,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        let task =
+            self.transition_training_task(task_id, Some(user_id), TrainingTaskStatus::Failed, now)?;
+        task.error = Some(error);
+        Ok(())
+    }
+
+    fn validate_training_task(&mut self, task_id: &str, now: u64) -> Result<(), TaskManagerError> {
+        self.transition_training_task(task_id, None, TrainingTaskStatus::Validated, now)?;
+        Ok(())
+    }
+
+    fn reject_training_task(
+        &mut self,
+        task_id: &str,
+        reason: String,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        let current = self.get_training_task(task_id)?.status;
+        if current != TrainingTaskStatus::Submitted {
+            return Err(TaskManagerError::InvalidTrainingTaskTransition {
+                task_id: task_id.to_string(),
+                from: current,
+                to: TrainingTaskStatus::Failed,
+            });
+        }
+        let task = self.transition_training_task(task_id, None, TrainingTaskStatus::Failed, now)?;
+        task.error = Some(reason);
+        Ok(())
+    }
+
+    fn cancel_training_task(&mut self, task_id: &str, now: u64) -> Result<(), TaskManagerError> {
+        self.transition_training_task(task_id, None, TrainingTaskStatus::Cancelled, now)?;
+        Ok(())
+    }
+
+    fn get_training_tasks_by_status(
+        &self,
+        status: TrainingTaskStatus,
+        query: &TrainingTaskQuery,
+    ) -> Result<TrainingTaskPage, TaskManagerError> {
+        self.page_training_tasks(query, |task| task.status == status)
+    }
+
+    fn get_training_tasks_by_model(
+        &self,
+        model_id: &str,
+        query: &TrainingTaskQuery,
+    ) -> Result<TrainingTaskPage, TaskManagerError> {
+        self.page_training_tasks(query, |task| task.model_id == model_id)
+    }
+
+    fn start_training_round(
+        &mut self,
+        request: TrainingRoundRequest,
+        now: u64,
+    ) -> Result<TrainingRound, TaskManagerError> {
+        let model = self.get_model_ref(&request.model_id)?;
+        let base_version = model.version.clone();
+        decode_weights(self.model_version_weights(&request.model_id, &base_version)?)?;
+        if parse_version(&request.target_version).is_none() {
+            return Err(TaskManagerError::invalid_argument(
+                "target_version",
+                "must be a semantic version such as 1.0.0",
+            ));
+        }
+        let published = self.model_versions.get(&request.model_id);
+        if base_version == request.target_version
+            || published.is_some_and(|versions| {
+                versions.iter().any(|published| published.model.version == request.target_version)
+            })
+        {
+            return Err(TaskManagerError::ModelVersionAlreadyExists {
+                model_id: request.model_id,
+                version: request.target_version,
+            });
+        }
+        if request.training_data.is_empty() || request.training_data.iter().any(Vec::is_empty) {
+            return Err(TaskManagerError::invalid_argument(
+                "training_data",
+                "needs at least one shard, and no shard can be empty",
+            ));
+        }
+        if request.min_submissions == 0
+            || request.min_submissions as usize > request.training_data.len()
+        {
+            return Err(TaskManagerError::invalid_argument(
+                "min_submissions",
+                "must be between 1 and the number of training data shards",
+            ));
+        }
+        request.config.validate()?;
+        request.aggregator.validate(request.min_submissions)?;
+        if request.clip_norm.is_some_and(|norm| !(norm.is_finite() && norm > 0.0)) {
+            return Err(TaskManagerError::invalid_argument(
+                "clip_norm",
+                "must be a finite, positive number",
+            ));
+        }
+
+        let sequence = self.next_training_round_id;
+        let round_id = format!("round-{}", sequence);
+        let task_ids: Vec<String> = (0..request.training_data.len())
+            .map(|index| format!("{}-task-{}", round_id, index))
+            .collect();
+        if let Some(task_id) = task_ids.iter().find(|id| self.training_tasks.contains_key(*id)) {
+            return Err(TaskManagerError::TrainingTaskAlreadyExists {
+                task_id: task_id.clone(),
+            });
+        }
+        self.next_training_round_id += 1;
+        for (task_id, training_data) in task_ids.iter().zip(request.training_data) {
+            let task = TrainingTask {
+                id: task_id.clone(),
+                model_id: request.model_id.clone(),
+                training_data,
+                config: request.config.clone(),
+                ..Default::default()
+            };
+            self.create_training_task(task, now)?;
+            self.training_tasks.get_mut(task_id).unwrap().round_id = Some(round_id.clone());
+        }
+        let round = TrainingRound {
+            id: round_id,
+            model_id: request.model_id,
+            base_version,
+            target_version: request.target_version,
+            task_ids,
+            min_submissions: request.min_submissions,
+            status: TrainingRoundStatus::Collecting,
+            aggregated_tasks: Vec::new(),
+            aggregator: request.aggregator,
+            clip_norm: request.clip_norm,
+            created_at: now,
+            updated_at: now,
+        };
+        self.training_rounds.insert(sequence, round.clone());
+        Ok(round)
+    }
+
+    fn finalize_training_round(
+        &mut self,
+        round_id: &str,
+        now: u64,
+    ) -> Result<TrainingRound, TaskManagerError> {
+        let round = self.get_open_training_round(round_id)?;
+        let submitted: Vec<&TrainingTask> = round
+            .task_ids
+            .iter()
+            .filter_map(|task_id| self.training_tasks.get(task_id))
+            .filter(|task| {
+                matches!(
+                    task.status,
+                    TrainingTaskStatus::Submitted | TrainingTaskStatus::Validated
+                )
+            })
+            .collect();
+        if submitted.len() < round.min_submissions as usize {
+            return Err(TaskManagerError::NotEnoughContributors {
+                required: round.min_submissions,
+                available: submitted.len() as u32,
+            });
+        }
+        let deltas = submitted
+            .iter()
+            .map(|task| {
+                let delta = decode_weights(task.model_weights.as_deref().unwrap_or_default())?;
+                Ok((delta, task.num_samples))
+            })
+            .collect::<Result<Vec<_>, TaskManagerError>>()?;
+        let aggregated_tasks: Vec<String> = submitted.iter().map(|task| task.id.clone()).collect();
+        let base = self.model_version_weights(&round.model_id, &round.base_version)?;
+        let base = decode_weights(base)?;
+        let aggregated = aggregate(round.aggregator, round.clip_norm, &base, &deltas)?;
+        let weights = encode_weights(&aggregated);
+
+        // Publish the result as a new version of the registry entry the round started from.
+        let current = self.get_model_ref(&round.model_id)?;
+        let mut model = if current.version == round.base_version {
+            current.clone()
         } else {
-            Err("Training task not found.".to_string())
+            self.get_model_version_mut(&round.model_id, &round.base_version)?
+                .model
+                .clone()
+        };
+        model.version = round.target_version.clone();
+        self.register_model(model, now)?;
+        self.upload_model_version_weights(&round.model_id, &round.target_version, weights)?;
+        self.promote_model_version(&round.model_id, &round.target_version, now)?;
+
+        for task_id in &round.task_ids {
+            let next = if aggregated_tasks.contains(task_id) {
+                TrainingTaskStatus::Validated
+            } else {
+                TrainingTaskStatus::Cancelled
+            };
+            // Tasks that already reached their final state are left as they are.
+            let _ = self.transition_training_task(task_id, None, next, now);
+        }
+        let round = self.get_training_round_mut(round_id)?;
+        round.status = TrainingRoundStatus::Published;
+        round.aggregated_tasks = aggregated_tasks;
+        round.updated_at = now;
+        Ok(round.clone())
+    }
+
+    fn cancel_training_round(&mut self, round_id: &str, now: u64) -> Result<(), TaskManagerError> {
+        let round = self.get_open_training_round(round_id)?;
+        for task_id in &round.task_ids {
+            let cancelled = TrainingTaskStatus::Cancelled;
+            let _ = self.transition_training_task(task_id, None, cancelled, now);
         }
+        let round = self.get_training_round_mut(round_id)?;
+        round.status = TrainingRoundStatus::Cancelled;
+        round.updated_at = now;
+        Ok(())
+    }
+
+    fn get_training_round(&self, round_id: &str) -> Result<TrainingRound, TaskManagerError> {
+        Self::training_round_sequence(round_id)
+            .and_then(|sequence| self.training_rounds.get(&sequence))
+            .cloned()
+            .ok_or_else(|| TaskManagerError::TrainingRoundNotFound {
+                round_id: round_id.to_string(),
+            })
+    }
+
+    fn begin_upload(
+        &mut self,
+        owner: Principal,
+        target: UploadTarget,
+        total_size: u64,
+        part_size: u64,
+        content_hash: Vec<u8>,
+        now: u64,
+    ) -> Result<UploadStatus, TaskManagerError> {
+        validate_upload_layout(total_size, part_size)?;
+        if content_hash.len() != 32 {
+            return Err(TaskManagerError::invalid_argument(
+                "content_hash",
+                "must be a SHA-256 digest",
+            ));
+        }
+        // Catch a wrong target now rather than after every part has been sent.
+        match &target {
+            UploadTarget::ModelWeights { model_id } => {
+                Self::check_accepts_weights(self.get_model_ref(model_id)?)?;
+            }
+            UploadTarget::ModelVersionWeights { model_id, version } => {
+                let current = self.get_model_ref(model_id)?;
+                if &current.version == version {
+                    Self::check_accepts_weights(current)?;
+                } else {
+                    let published = self.get_model_version_mut(model_id, version)?;
+                    Self::check_accepts_weights(&published.model)?;
+                }
+            }
+            UploadTarget::TrainingData { task } => {
+                if self.training_tasks.contains_key(&task.id) {
+                    return Err(TaskManagerError::TrainingTaskAlreadyExists {
+                        task_id: task.id.clone(),
+                    });
+                }
+                task.config.validate()?;
+            }
+            UploadTarget::TrainingResults { task_id, .. } => {
+                let user_id = self.find_user_id(&owner)?;
+                let task = self.get_training_task(task_id)?;
+                if task.assignee.as_deref() != Some(user_id.as_str()) {
+                    return Err(TaskManagerError::TrainingTaskNotAssigned {
+                        task_id: task_id.clone(),
+                        user_id,
+                    });
+                }
+            }
+        }
+
+        self.drop_expired_uploads(now);
+        let open = self.uploads.values().filter(|upload| upload.owner == owner).count();
+        if open >= MAX_OPEN_UPLOADS_PER_OWNER {
+            return Err(TaskManagerError::TooManyOpenUploads {
+                limit: MAX_OPEN_UPLOADS_PER_OWNER,
+            });
+        }
+        let pending: u64 = self.uploads.values().map(|upload| upload.total_size).sum();
+        if pending + total_size > MAX_PENDING_UPLOAD_BYTES {
+            return Err(TaskManagerError::UploadStorageFull);
+        }
+        let sequence = self.next_upload_id;
+        self.next_upload_id += 1;
+        let upload = Upload {
+            id: format!("upload-{}", sequence),
+            owner,
+            target,
+            total_size,
+            part_size,
+            content_hash,
+            parts: BTreeMap::new(),
+            created_at: now,
+            updated_at: now,
+        };
+        let status = upload.status();
+        self.uploads.insert(sequence, upload);
+        Ok(status)
+    }
+
+    fn upload_part(
+        &mut self,
+        caller: &Principal,
+        upload_id: &str,
+        index: u32,
+        data: Vec<u8>,
+        part_hash: Vec<u8>,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        let upload = self.get_upload_mut(caller, upload_id, now)?;
+        if index >= upload.total_parts() {
+            return Err(TaskManagerError::invalid_argument(
+                "index",
+                &format!("must be below {}", upload.total_parts()),
+            ));
+        }
+        if data.len() as u64 != upload.part_len(index) {
+            return Err(TaskManagerError::invalid_argument(
+                "data",
+                &format!("part {} must be {} bytes", index, upload.part_len(index)),
+            ));
+        }
+        if content_hash(&data) != part_hash {
+            return Err(TaskManagerError::UploadIntegrityFailed {
+                upload_id: upload_id.to_string(),
+                part: Some(index),
+            });
+        }
+        // Sending a part again replaces it, so interrupted parts can simply be retried.
+        upload.parts.insert(index, data);
+        upload.updated_at = now;
+        Ok(())
+    }
+
+    fn get_upload(
+        &self,
+        caller: &Principal,
+        upload_id: &str,
+    ) -> Result<UploadStatus, TaskManagerError> {
+        let upload = Self::upload_sequence(upload_id)
+            .and_then(|sequence| self.uploads.get(&sequence))
+            .ok_or_else(|| TaskManagerError::UploadNotFound {
+                upload_id: upload_id.to_string(),
+            })?;
+        if &upload.owner != caller {
+            return Err(TaskManagerError::NotUploadOwner {
+                upload_id: upload_id.to_string(),
+            });
+        }
+        Ok(upload.status())
+    }
+
+    fn commit_upload(
+        &mut self,
+        caller: &Principal,
+        upload_id: &str,
+        now: u64,
+    ) -> Result<(), TaskManagerError> {
+        let upload = self.get_upload_mut(caller, upload_id, now)?;
+        if upload.parts.len() as u32 != upload.total_parts() {
+            return Err(TaskManagerError::UploadIncomplete {
+                upload_id: upload_id.to_string(),
+                received: upload.parts.len() as u32,
+                expected: upload.total_parts(),
+            });
+        }
+        let mut bytes = Vec::with_capacity(upload.total_size as usize);
+        upload.parts.values().for_each(|part| bytes.extend_from_slice(part));
+        if content_hash(&bytes) != upload.content_hash {
+            return Err(TaskManagerError::UploadIntegrityFailed {
+                upload_id: upload_id.to_string(),
+                part: None,
+            });
+        }
+
+        // A target that rejects the bytes leaves the upload in place so the commit can be
+        // retried once the problem is fixed.
+        match upload.target.clone() {
+            UploadTarget::ModelWeights { model_id } => {
+                self.upload_model_weights(&model_id, bytes)?;
+            }
+            UploadTarget::ModelVersionWeights { model_id, version } => {
+                self.upload_model_version_weights(&model_id, &version, bytes)?;
+            }
+            UploadTarget::TrainingData { mut task } => {
+                task.training_data = bytes;
+                self.create_training_task(*task, now)?;
+            }
+            UploadTarget::TrainingResults {
+                task_id,
+                num_samples,
+            } => {
+                let user_id = self.find_user_id(caller)?;
+                self.submit_training_results(&user_id, &task_id, bytes, num_samples, now)?;
+            }
+        }
+        if let Some(sequence) = Self::upload_sequence(upload_id) {
+            self.uploads.remove(&sequence);
+        }
+        Ok(())
+    }
+
+    fn abort_upload(
+        &mut self,
+        caller: &Principal,
+        upload_id: &str,
+    ) -> Result<(), TaskManagerError> {
+        self.get_upload(caller, upload_id)?;
+        if let Some(sequence) = Self::upload_sequence(upload_id) {
+            self.uploads.remove(&sequence);
+        }
+        Ok(())
+    }
+
+    fn drop_expired_uploads(&mut self, now: u64) -> usize {
+        let before = self.uploads.len();
+        self.uploads.retain(|_, upload| !upload.is_expired(now));
+        before - self.uploads.len()
+    }
+
+    fn read_download(
+        &self,
+        source: &DownloadSource,
+        offset: u64,
+        length: u64,
+    ) -> Result<DownloadSlice, TaskManagerError> {
+        // Stored hashes are used where they exist, since hashing a whole model on every read
+        // would not fit in a query's instruction budget.
+        let (blob, hash) = match source {
+            DownloadSource::ModelChunk { chunk_id } => {
+                let chunk = self.model_chunks.get(chunk_id).ok_or_else(|| {
+                    TaskManagerError::ModelChunkNotFound {
+                        chunk_id: chunk_id.clone(),
+                    }
+                })?;
+                (&chunk.data, chunk.content_hash.clone())
+            }
+            DownloadSource::ModelWeights { model_id } => {
+                let model = self.get_model_ref(model_id)?;
+                let blob = self.model_version_weights(model_id, &model.version)?;
+                (blob, model.weight_hash.clone())
+            }
+            DownloadSource::ModelVersionWeights { model_id, version } => {
+                let blob = self.model_version_weights(model_id, version)?;
+                let model = self.get_model_ref(model_id)?;
+                let hash = if &model.version == version {
+                    model.weight_hash.clone()
+                } else {
+                    self.model_versions
+                        .get(model_id)
+                        .and_then(|versions| {
+                            versions.iter().find(|published| &published.model.version == version)
+                        })
+                        .map(|published| published.model.weight_hash.clone())
+                        .unwrap_or_default()
+                };
+                (blob, hash)
+            }
+            DownloadSource::TrainingResults { task_id } => {
+                let task = self.training_tasks.get(task_id).ok_or_else(|| {
+                    TaskManagerError::TrainingTaskNotFound {
+                        task_id: task_id.clone(),
+                    }
+                })?;
+                let blob = task.model_weights.as_ref().ok_or_else(|| {
+                    TaskManagerError::invalid_argument("task_id", "has no submitted weights")
+                })?;
+                (blob, task.results_hash.clone())
+            }
+        };
+        let range = slice_range(blob.len() as u64, offset, length)?;
+        let data = blob[range].to_vec();
+        Ok(DownloadSlice {
+            slice_hash: content_hash(&data),
+            data,
+            offset,
+            total_size: blob.len() as u64,
+            content_hash: hash,
+        })
     }
 }
 
 impl TaskManagerImpl {
+    // Models registered before versioning have no version and no published record, which
+    // promotion and rollback both rely on. Give each such model a record of its live entry,
+    // numbered `LEGACY_MODEL_VERSION` when it has none, so it can be archived like any other.
+    pub fn backfill_model_versions(&mut self) {
+        for model in self.models.values_mut() {
+            if model.version.is_empty() {
+                model.version = LEGACY_MODEL_VERSION.to_string();
+            }
+            if model.weight_hash.is_empty() {
+                if let Some(weights) = self.model_weights.get(&model.id) {
+                    model.weight_hash = content_hash(weights);
+                }
+            }
+            let versions = self.model_versions.entry(model.id.clone()).or_default();
+            if !versions.iter().any(|published| published.model.version == model.version) {
+                versions.push(ModelVersion {
+                    model: model.clone(),
+                    published_at: model.created_at,
+                });
+            }
+        }
+    }
+
+    // Results submitted before their hash was stored are hashed once here rather than on
+    // every `read_download`.
+    pub fn backfill_results_hashes(&mut self) {
+        for task in self.training_tasks.values_mut() {
+            match &task.model_weights {
+                Some(weights) if task.results_hash.is_empty() => {
+                    task.results_hash = content_hash(weights)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Install an already-built backend for a model, replacing any previously loaded one.
+    pub fn set_text_generator(&mut self, model_id: &str, generator: Box<dyn TextGenerator>) {
+        self.text_generators.insert(model_id.to_string(), generator);
+    }
+
+    // The model a completion request targets: the named one, which must be active, or else
+    // the active model with the lowest id so the choice does not depend on map order.
+    fn resolve_completion_model(
+        &self,
+        request: &CompletionRequest,
+    ) -> Result<String, TaskManagerError> {
+        match &request.model_id {
+            Some(model_id) => {
+                if !self.get_model_ref(model_id)?.active {
+                    return Err(TaskManagerError::ModelNotActive {
+                        model_id: model_id.clone(),
+                    });
+                }
+                Ok(model_id.clone())
+            }
+            None => self
+                .models
+                .values()
+                .filter(|model| model.active)
+                .map(|model| model.id.clone())
+                .min()
+                .ok_or(TaskManagerError::NoActiveModels),
+        }
+    }
+
+    // Assign the next completion id and keep the record for `get_completion`.
+    fn store_completion(&mut self, mut completion: Completion) -> Completion {
+        let sequence = self.next_completion_id;
+        self.next_completion_id += 1;
+        completion.id = format!("cmpl-{}", sequence);
+        self.completions.insert(sequence, completion.clone());
+        while self.completions.len() > MAX_STORED_COMPLETIONS {
+            self.completions.pop_first();
+        }
+        completion
+    }
+
+    fn completion_job_sequence(job_id: &str) -> Option<u64> {
+        job_id.strip_prefix("job-")?.parse().ok()
+    }
+
+    // Fail unfinished jobs older than `COMPLETION_JOB_EXPIRY_NS`.
+    fn expire_completion_jobs(&mut self, now: u64) {
+        for job in self.completion_jobs.values_mut() {
+            if !job.is_finished() && now >= job.created_at.saturating_add(COMPLETION_JOB_EXPIRY_NS)
+            {
+                job.status = CompletionJobStatus::Failed;
+                job.error = Some("expired before a worker finished it".to_string());
+                job.lease_expires_at = None;
+                job.updated_at = now;
+            }
+        }
+    }
+
+    // Finished jobs only need to stay around long enough to be polled and reviewed; the oldest
+    // are dropped beyond `MAX_STORED_COMPLETIONS`, unpaid if still unreviewed.
+    fn evict_completion_jobs(&mut self) {
+        let excess = self.completion_jobs.len().saturating_sub(MAX_STORED_COMPLETIONS);
+        let evicted: Vec<u64> = self
+            .completion_jobs
+            .iter()
+            .filter(|(_, job)| job.is_finished())
+            .map(|(sequence, _)| *sequence)
+            .take(excess)
+            .collect();
+        for sequence in evicted {
+            self.completion_jobs.remove(&sequence);
+        }
+    }
+
+    fn get_completion_job_mut(
+        &mut self,
+        job_id: &str,
+    ) -> Result<&mut CompletionJob, TaskManagerError> {
+        Self::completion_job_sequence(job_id)
+            .and_then(move |sequence| self.completion_jobs.get_mut(&sequence))
+            .ok_or_else(|| TaskManagerError::CompletionJobNotFound {
+                job_id: job_id.to_string(),
+            })
+    }
+
+    // Move a task to `next`, checking the lifecycle and, for worker calls, that `assignee`
+    // holds the task.
+    fn transition_training_task(
+        &mut self,
+        task_id: &str,
+        assignee: Option<&str>,
+        next: TrainingTaskStatus,
+        now: u64,
+    ) -> Result<&mut TrainingTask, TaskManagerError> {
+        let task = self.training_tasks.get_mut(task_id).ok_or_else(|| {
+            TaskManagerError::TrainingTaskNotFound {
+                task_id: task_id.to_string(),
+            }
+        })?;
+        if let Some(user_id) = assignee {
+            if task.assignee.as_deref() != Some(user_id) {
+                return Err(TaskManagerError::TrainingTaskNotAssigned {
+                    task_id: task_id.to_string(),
+                    user_id: user_id.to_string(),
+                });
+            }
+        }
+        if !task.status.can_transition_to(next) {
+            return Err(TaskManagerError::InvalidTrainingTaskTransition {
+                task_id: task_id.to_string(),
+                from: task.status,
+                to: next,
+            });
+        }
+        task.status = next;
+        task.updated_at = now;
+        if next.is_finished() {
+            task.finished_at = Some(now);
+        }
+        Ok(task)
+    }
+
+    // One page of the tasks accepted by `include`, oldest first, starting after the cursor.
+    fn page_training_tasks(
+        &self,
+        query: &TrainingTaskQuery,
+        include: impl Fn(&TrainingTask) -> bool,
+    ) -> Result<TrainingTaskPage, TaskManagerError> {
+        if query.limit == 0 || query.limit > MAX_TRAINING_TASK_PAGE_SIZE {
+            return Err(TaskManagerError::invalid_argument(
+                "limit",
+                &format!("must be between 1 and {}", MAX_TRAINING_TASK_PAGE_SIZE),
+            ));
+        }
+        let after = query.cursor.as_deref().map(ModelQuery::decode_cursor).transpose()?;
+
+        let mut matching: Vec<((u64, String), &TrainingTask)> = self
+            .training_tasks
+            .values()
+            .filter(|task| include(task))
+            .map(|task| ((task.created_at, task.id.clone()), task))
+            .collect();
+        matching.sort_by(|a, b| a.0.cmp(&b.0));
+        let total = matching.len() as u64;
+
+        let start = after.map_or(0, |after| matching.partition_point(|(key, _)| *key <= after));
+        let page = &matching[start..matching.len().min(start + query.limit as usize)];
+        let next_cursor = match page.last() {
+            Some((key, _)) if start + page.len() < matching.len() => {
+                Some(ModelQuery::encode_cursor(key))
+            }
+            _ => None,
+        };
+        Ok(TrainingTaskPage {
+            tasks: page.iter().map(|(_, task)| TrainingTaskSummary::from(*task)).collect(),
+            next_cursor,
+            total,
+        })
+    }
+
+    // One page of the models accepted by both `include` and the query's filters, in the
+    // query's order, starting after its cursor.
+    fn page_models(
+        &self,
+        query: &ModelQuery,
+        include: impl Fn(&Model) -> bool,
+    ) -> Result<ModelPage, TaskManagerError> {
+        if query.limit == 0 || query.limit > MAX_MODEL_PAGE_SIZE {
+            return Err(TaskManagerError::invalid_argument(
+                "limit",
+                &format!("must be between 1 and {}", MAX_MODEL_PAGE_SIZE),
+            ));
+        }
+        let after = query.cursor.as_deref().map(ModelQuery::decode_cursor).transpose()?;
+        let order = |a: &(u64, String), b: &(u64, String)| {
+            if query.descending {
+                b.cmp(a)
+            } else {
+                a.cmp(b)
+            }
+        };
+
+        let mut matching: Vec<((u64, String), &Model)> = self
+            .models
+            .values()
+            .filter(|model| include(model) && query.matches(model))
+            .map(|model| (query.sort_key(model), model))
+            .collect();
+        matching.sort_by(|a, b| order(&a.0, &b.0));
+        let total = matching.len() as u64;
+
+        let start = after.map_or(0, |after| {
+            matching.partition_point(|(key, _)| order(key, &after) != Ordering::Greater)
+        });
+        let page = &matching[start..matching.len().min(start + query.limit as usize)];
+        let next_cursor = match page.last() {
+            Some((key, _)) if start + page.len() < matching.len() => {
+                Some(ModelQuery::encode_cursor(key))
+            }
+            _ => None,
+        };
+        Ok(ModelPage {
+            models: page.iter().map(|(_, model)| (*model).clone()).collect(),
+            next_cursor,
+            total,
+        })
+    }
+
+    // Resources committed to a model. Pledges are capped by each user's resources, so no
+    // capacity is counted towards more than one model.
+    fn model_capacity(&self, model_id: &str) -> u64 {
+        self.pledges
+            .values()
+            .filter_map(|pledges| pledges.get(model_id))
+            .sum()
+    }
+
+    // Registered users with a pledge to `model_id`, and the amount each pledged.
+    fn model_pledges(&self, model_id: &str) -> Vec<(&str, u64)> {
+        self.pledges
+            .iter()
+            .filter(|(user_id, _)| self.users.contains_key(user_id.as_str()))
+            .filter_map(|(user_id, pledges)| {
+                let amount = pledges.get(model_id).copied().filter(|&amount| amount > 0)?;
+                Some((user_id.as_str(), amount))
+            })
+            .collect()
+    }
+
+    // Total a user has pledged, optionally leaving out their pledge to one model.
+    fn pledged_by(&self, user_id: &str, excluding: Option<&str>) -> u64 {
+        self.pledges
+            .get(user_id)
+            .into_iter()
+            .flatten()
+            .filter(|(model_id, _)| Some(model_id.as_str()) != excluding)
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+
+    fn record_model_event(
+        &mut self,
+        model_id: String,
+        kind: ModelEventKind,
+        capacity: u64,
+        min_resources: u64,
+        now: u64,
+    ) -> ModelEvent {
+        let event = ModelEvent {
+            sequence: self.next_model_event,
+            model_id,
+            kind,
+            capacity,
+            min_resources,
+            timestamp: now,
+        };
+        self.next_model_event += 1;
+        self.model_events.push_back(event.clone());
+        while self.model_events.len() > MAX_MODEL_EVENTS {
+            self.model_events.pop_front();
+        }
+        event
+    }
+
+    // Only hashes are kept, so a leaked snapshot of the state does not leak usable keys.
+    fn api_key_hash(key: &str) -> String {
+        content_hash(key.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn upload_sequence(upload_id: &str) -> Option<u64> {
+        upload_id.strip_prefix("upload-")?.parse().ok()
+    }
+
+    // An upload the caller began that has not expired.
+    fn get_upload_mut(
+        &mut self,
+        caller: &Principal,
+        upload_id: &str,
+        now: u64,
+    ) -> Result<&mut Upload, TaskManagerError> {
+        let not_found = || TaskManagerError::UploadNotFound {
+            upload_id: upload_id.to_string(),
+        };
+        let upload = Self::upload_sequence(upload_id)
+            .and_then(move |sequence| self.uploads.get_mut(&sequence))
+            .ok_or_else(not_found)?;
+        if upload.is_expired(now) {
+            return Err(not_found());
+        }
+        if &upload.owner != caller {
+            return Err(TaskManagerError::NotUploadOwner {
+                upload_id: upload_id.to_string(),
+            });
+        }
+        Ok(upload)
+    }
+
+    fn training_round_sequence(round_id: &str) -> Option<u64> {
+        round_id.strip_prefix("round-")?.parse().ok()
+    }
+
+    fn get_training_round_mut(
+        &mut self,
+        round_id: &str,
+    ) -> Result<&mut TrainingRound, TaskManagerError> {
+        Self::training_round_sequence(round_id)
+            .and_then(move |sequence| self.training_rounds.get_mut(&sequence))
+            .ok_or_else(|| TaskManagerError::TrainingRoundNotFound {
+                round_id: round_id.to_string(),
+            })
+    }
+
+    fn get_open_training_round(&self, round_id: &str) -> Result<TrainingRound, TaskManagerError> {
+        let round = self.get_training_round(round_id)?;
+        if round.status != TrainingRoundStatus::Collecting {
+            return Err(TaskManagerError::TrainingRoundClosed {
+                round_id: round_id.to_string(),
+            });
+        }
+        Ok(round)
+    }
+
+    // Weights of a published version, whether it is currently promoted or archived.
+    fn model_version_weights(
+        &self,
+        model_id: &str,
+        version: &str,
+    ) -> Result<&Vec<u8>, TaskManagerError> {
+        let weights = if self.get_model_ref(model_id)?.version == version {
+            self.model_weights.get(model_id)
+        } else {
+            self.version_weights.get(&Self::version_key(model_id, version))
+        };
+        weights.ok_or_else(|| TaskManagerError::ModelWeightsNotFound {
+            model_id: model_id.to_string(),
+        })
+    }
+
+    // GPT-Neo always runs rust-bert's pretrained checkpoint, which it can only load from files,
+    // so weights uploaded for it would never change its output.
+    fn check_accepts_weights(model: &Model) -> Result<(), TaskManagerError> {
+        if model.backend == InferenceBackend::GptNeo {
+            return Err(TaskManagerError::invalid_argument(
+                "weights",
+                "GPT-Neo models run their pretrained weights and cannot take uploaded ones",
+            ));
+        }
+        Ok(())
+    }
+
+    // A version can go live once its weights are stored, or right away for GPT-Neo.
+    fn is_promotable(&self, model_id: &str, version: &Model) -> bool {
+        version.backend == InferenceBackend::GptNeo
+            || self
+                .version_weights
+                .contains_key(&Self::version_key(model_id, &version.version))
+    }
+
+    fn version_key(model_id: &str, version: &str) -> String {
+        format!("{}@{}", model_id, version)
+    }
+
+    fn get_model_version_mut(
+        &mut self,
+        model_id: &str,
+        version: &str,
+    ) -> Result<&mut ModelVersion, TaskManagerError> {
+        self.model_versions
+            .get_mut(model_id)
+            .and_then(|versions| {
+                versions
+                    .iter_mut()
+                    .find(|published| published.model.version == version)
+            })
+            .ok_or_else(|| TaskManagerError::ModelVersionNotFound {
+                model_id: model_id.to_string(),
+                version: version.to_string(),
+            })
+    }
+
+    // Refill every chunk of the model from its current weights while keeping each chunk's
+    // index, replica and holder, so promoting a version does not reshuffle assignments. The
+    // weights are split into as many chunks as before, and results computed on the previous
+    // weights are discarded.
+    fn reshard_model_chunks(&mut self, model_id: &str, now: u64) {
+        let weights = match self.model_weights.get(model_id) {
+            Some(weights) => weights,
+            None => return,
+        };
+        let total_chunks = self
+            .model_chunks
+            .values()
+            .filter(|chunk| chunk.model_id == model_id)
+            .map(|chunk| chunk.total_chunks as usize)
+            .max()
+            .unwrap_or(0);
+        if total_chunks == 0 {
+            return;
+        }
+        let chunk_size = weights.len().div_ceil(total_chunks).max(1);
+        for chunk in self
+            .model_chunks
+            .values_mut()
+            .filter(|chunk| chunk.model_id == model_id)
+        {
+            let start = (chunk.index as usize * chunk_size).min(weights.len());
+            let end = (start + chunk_size).min(weights.len());
+            chunk.data = weights[start..end].to_vec();
+            chunk.offset = start as u64;
+            chunk.content_hash = content_hash(&chunk.data);
+            // Holders have to compute their chunk again, including those that already submitted.
+            chunk.lease_expires_at = Some(now + CHUNK_LEASE_DURATION_NS);
+        }
+        let config = self.verification_config.clone();
+        for verification in self
+            .shard_verifications
+            .values_mut()
+            .filter(|verification| verification.model_id == model_id)
+        {
+            let shard_id = verification.shard_id.clone();
+            *verification = ShardVerification::new(shard_id, model_id.to_string(), &config);
+        }
+    }
+
+    fn get_user_ref(&self, user_id: &str) -> Result<&User, TaskManagerError> {
+        self.users
+            .get(user_id)
+            .ok_or_else(|| TaskManagerError::UserNotFound {
+                user_id: user_id.to_string(),
+            })
+    }
+
+    fn get_user_mut(&mut self, user_id: &str) -> Result<&mut User, TaskManagerError> {
+        self.users
+            .get_mut(user_id)
+            .ok_or_else(|| TaskManagerError::UserNotFound {
+                user_id: user_id.to_string(),
+            })
+    }
+
+    fn get_model_ref(&self, model_id: &str) -> Result<&Model, TaskManagerError> {
+        self.models
+            .get(model_id)
+            .ok_or_else(|| TaskManagerError::ModelNotFound {
+                model_id: model_id.to_string(),
+            })
+    }
+
+    fn get_model_mut(&mut self, model_id: &str) -> Result<&mut Model, TaskManagerError> {
+        self.models
+            .get_mut(model_id)
+            .ok_or_else(|| TaskManagerError::ModelNotFound {
+                model_id: model_id.to_string(),
+            })
+    }
+
+    // Look up a chunk that `user_id` currently holds an unexpired lease on.
+    fn leased_chunk_mut(
+        &mut self,
+        chunk_id: &str,
+        user_id: &str,
+        now: u64,
+    ) -> Result<&mut ModelChunk, TaskManagerError> {
+        let chunk = self
+            .model_chunks
+            .get_mut(chunk_id)
+            .ok_or_else(|| TaskManagerError::ModelChunkNotFound {
+                chunk_id: chunk_id.to_string(),
+            })?;
+        if chunk.user_id != user_id {
+            return Err(TaskManagerError::ChunkNotAssigned {
+                chunk_id: chunk_id.to_string(),
+                user_id: user_id.to_string(),
+            });
+        }
+        match chunk.lease_expires_at {
+            Some(expires_at) if expires_at > now => Ok(chunk),
+            Some(_) => Err(TaskManagerError::LeaseExpired {
+                chunk_id: chunk_id.to_string(),
+            }),
+            None => Err(TaskManagerError::ChunkAlreadySubmitted {
+                chunk_id: chunk_id.to_string(),
+            }),
+        }
+    }
+
+    // Orders pledges by spare capacity, i.e. the highest reputation-weighted pledge per chunk of
+    // the model already held first, falling back to id so assignment is deterministic.
+    fn compare_spare_capacity(
+        a: (&str, u64),
+        b: (&str, u64),
+        held: &HashMap<String, u64>,
+        reputations: &HashMap<String, Reputation>,
+    ) -> Ordering {
+        let weight = |(user_id, pledged): (&str, u64)| {
+            let score = reputations
+                .get(user_id)
+                .map_or_else(|| Reputation::default().score, |r| r.score);
+            pledged as u128 * score as u128
+        };
+        let a_load = held.get(a.0).copied().unwrap_or(0) + 1;
+        let b_load = held.get(b.0).copied().unwrap_or(0) + 1;
+        (weight(b) * a_load as u128)
+            .cmp(&(weight(a) * b_load as u128))
+            .then_with(|| a.0.cmp(b.0))
+    }
+
+    // Pick the user pledging to `model_id` with the most spare capacity for it, skipping
+    // `exclude` (the expired holder and anyone else already computing the same shard).
+    fn pick_lease_target(&self, model_id: &str, exclude: &[String]) -> Option<String> {
+        let mut held: HashMap<String, u64> = HashMap::new();
+        for chunk in self.model_chunks.values() {
+            if chunk.model_id == model_id && chunk.lease_expires_at.is_some() {
+                *held.entry(chunk.user_id.clone()).or_insert(0) += 1;
+            }
+        }
+        self.model_pledges(model_id)
+            .into_iter()
+            .filter(|(user_id, _)| !exclude.iter().any(|excluded| excluded == user_id))
+            .min_by(|&a, &b| Self::compare_spare_capacity(a, b, &held, &self.reputations))
+            .map(|(user_id, _)| user_id.to_string())
+    }
+
+    // Assign each of `shard_count` shards to `redundancy` distinct users pledging to the model,
+    // always handing the next shard to whoever has the most spare capacity so totals stay
+    // proportional to pledges. Returns the holders of each shard, in replica order.
+    fn allocate_shards(
+        mut contributors: Vec<(&str, u64)>,
+        reputations: &HashMap<String, Reputation>,
+        shard_count: usize,
+        redundancy: usize,
+    ) -> Result<Vec<Vec<String>>, TaskManagerError> {
+        if contributors.is_empty() || contributors.len() < redundancy {
+            return Err(TaskManagerError::NotEnoughContributors {
+                required: redundancy.max(1) as u32,
+                available: contributors.len() as u32,
+            });
+        }
+
+        let mut held: HashMap<String, u64> = HashMap::new();
+        let mut holders = Vec::with_capacity(shard_count);
+        for _ in 0..shard_count {
+            contributors
+                .sort_by(|&a, &b| Self::compare_spare_capacity(a, b, &held, reputations));
+            let shard_holders: Vec<String> = contributors
+                .iter()
+                .take(redundancy)
+                .map(|(user_id, _)| user_id.to_string())
+                .collect();
+            for user_id in &shard_holders {
+                *held.entry(user_id.clone()).or_insert(0) += 1;
+            }
+            holders.push(shard_holders);
+        }
+        Ok(holders)
+    }
+
     fn calculate_rewards(&mut self, user_id: &str, completed_chunks: usize) -> u64 {
         let reward = completed_chunks as u64;
         if let Some(user) = self.users.get_mut(user_id) {
@@ -224,4 +2275,19 @@ impl TaskManagerImpl {
         }
         reward
     }
+
+    fn slash_rewards(&mut self, user_id: &str) -> u64 {
+        match self.users.get_mut(user_id) {
+            Some(user) => {
+                let slashed = slash_amount(user.rewards);
+                user.rewards -= slashed;
+                slashed
+            }
+            None => 0,
+        }
+    }
 }
+
+#[cfg(test)]
+#[path = "task_manager_impl_tests.rs"]
+mod tests;
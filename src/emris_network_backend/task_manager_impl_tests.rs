@@ -1,8 +1,48 @@
-use crate::model_chunk::ModelChunk;
+use crate::completion::{CompletionRequest, FinishReason};
+use crate::completion_job::{
+    CompletionJobStatus, CompletionJobUpdate, COMPLETION_JOB_EXPIRY_NS,
+    COMPLETION_JOB_LEASE_DURATION_NS, MAX_COMPLETION_BYTES_PER_TOKEN, MAX_PENDING_COMPLETION_JOBS,
+};
+use crate::download::{DownloadSource, MAX_DOWNLOAD_LENGTH};
+use crate::errors::TaskManagerError;
+use crate::http::{
+    download_route, error_status, handle_api_query, handle_api_update, request_path,
+    serve_download, stream_download, HttpRequest, StreamingCallbackToken,
+};
+use crate::inference::{
+    log_probability, sample_token, Generation, InferenceBackend, SamplingRng, TextGenerator,
+};
+use crate::model::{
+    CapacityPolicy, Model, ModelArchitecture, ModelEvent, ModelEventKind, ModelQuery,
+    ModelSortKey, MAX_MODEL_PAGE_SIZE,
+};
+use crate::model_chunk::{content_hash, ModelChunk};
+use crate::onnx_generator::last_position_logits;
+use crate::rate_limit::{RateLimitConfig, RateLimitTier};
+use crate::reputation::Reputation;
+use crate::stable_state::{decode_state, encode_state, STATE_VERSION};
 use crate::task_manager::TaskManagerInterface;
-use crate::task_manager_impl::{Model, TaskManagerImpl};
-use crate::training_task::TrainingTask;
-use crate::user::User;
+use crate::task_manager_impl::{
+    TaskManagerImpl, CHUNK_LEASE_DURATION_NS, LEGACY_MODEL_VERSION,
+};
+use crate::tokenizer::GPT2Tokenizer;
+use crate::training_round::{
+    aggregate, decode_weights, encode_weights, Aggregator, TrainingRoundRequest,
+    TrainingRoundStatus,
+};
+use crate::training_task::{
+    LearningRateSchedule, Optimizer, TrainingConfig, TrainingTask, TrainingTaskPage,
+    TrainingTaskQuery, TrainingTaskStatus, MAX_BATCH_SIZE, MAX_NUM_SAMPLES,
+    MAX_TRAINING_TASK_PAGE_SIZE,
+};
+use crate::upload::{
+    UploadStatus, UploadTarget, MAX_OPEN_UPLOADS_PER_OWNER, MAX_UPLOAD_PART_SIZE, MAX_UPLOAD_SIZE,
+    MIN_UPLOAD_PART_SIZE, UPLOAD_EXPIRY_NS,
+};
+use crate::user::{ResourcePledge, User};
+use crate::verification::{VerificationConfig, VerificationStatus};
+use ic_cdk::export::candid::Func;
+use ic_cdk::export::Principal;
 
 #[test]
 fn test_register_user() {
@@ -12,10 +52,33 @@ fn test_register_user() {
         resources: 100,
         rewards: 0,
         rate_limit_tokens: 10,
+        owner: None,
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
     };
     let result = task_manager.register_user(user.clone());
     assert_eq!(result, Ok(user.id.clone()));
     assert!(task_manager.users.contains_key(&user.id));
+
+    // Rewards and rate-limit state cannot be declared at registration.
+    let user = User {
+        id: "user2".to_string(),
+        rewards: 1_000_000,
+        rate_limit_tokens: u64::MAX,
+        rate_limit_refilled_at: u64::MAX,
+        ..user
+    };
+    let reputation = Reputation {
+        rejected: 5,
+        ..Default::default()
+    };
+    task_manager.reputations.insert(user.id.clone(), reputation);
+    task_manager.register_user(user).unwrap();
+    let registered = &task_manager.users["user2"];
+    assert_eq!(registered.rewards, 0);
+    assert_eq!(registered.rate_limit_tokens, RateLimitConfig::default().free.capacity);
+    assert_eq!(registered.rate_limit_refilled_at, 0);
+    assert_eq!(task_manager.get_reputation("user2").unwrap().rejected, 0);
 }
 
 #[test]
@@ -26,6 +89,9 @@ fn test_update_user_resources() {
         resources: 100,
         rewards: 0,
         rate_limit_tokens: 10,
+        owner: None,
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
     };
     task_manager.users.insert(user.id.clone(), user.clone());
     let result = task_manager.update_user_resources(&user.id, 200);
@@ -40,10 +106,24 @@ fn test_register_model() {
         id: "model1".to_string(),
         min_resources: 500,
         active: false,
+        version: "1.0.0".to_string(),
+        ..Default::default()
     };
-    let result = task_manager.register_model(model.clone());
+    let result = task_manager.register_model(model.clone(), 0);
     assert_eq!(result, Ok(model.id.clone()));
     assert!(task_manager.models.contains_key(&model.id));
+
+    // Registration never activates a model; that takes enough pledged capacity.
+    let eager = Model {
+        id: "model2".to_string(),
+        active: true,
+        degraded: true,
+        ..model
+    };
+    task_manager.register_model(eager, 0).unwrap();
+    let registered = task_manager.get_model("model2").unwrap();
+    assert!(!registered.active && !registered.degraded);
+    assert!(task_manager.activate_model("model2").is_err());
 }
 
 #[test]
@@ -53,6 +133,8 @@ fn test_activate_deactivate_model() {
         id: "model1".to_string(),
         min_resources: 500,
         active: false,
+        version: "1.0.0".to_string(),
+        ..Default::default()
     };
     task_manager.models.insert(model.id.clone(), model.clone());
     let user = User {
@@ -60,9 +142,22 @@ fn test_activate_deactivate_model() {
         resources: 1000,
         rewards: 0,
         rate_limit_tokens: 10,
+        owner: None,
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
     };
     task_manager.users.insert(user.id.clone(), user.clone());
     let result_activate = task_manager.activate_model(&model.id);
+    assert_eq!(
+        result_activate,
+        Err(TaskManagerError::InsufficientResources {
+            model_id: model.id.clone(),
+            required: 500,
+            available: 0,
+        })
+    );
+    task_manager.pledge_resources(&user.id, &model.id, 500).unwrap();
+    let result_activate = task_manager.activate_model(&model.id);
     assert_eq!(result_activate, Ok(()));
     assert!(task_manager.models.get(&model.id).unwrap().active);
     let result_deactivate = task_manager.deactivate_model(&model.id);
@@ -78,8 +173,9 @@ fn test_create_training_task() {
         model_id: "model1".to_string(),
         training_data: vec![0u8; 1024],
         model_weights: None,
+        ..Default::default()
     };
-    let result = task_manager.create_training_task(training_task.clone());
+    let result = task_manager.create_training_task(training_task.clone(), 0);
     assert_eq!(result, Ok(training_task.id.clone()));
     assert!(task_manager.training_tasks.contains_key(&training_task.id));
 }
@@ -92,18 +188,22 @@ fn test_submit_training_results() {
         model_id: "model1".to_string(),
         training_data: vec![0u8; 1024],
         model_weights: None,
+        ..Default::default()
     };
-    task_manager
-        .training_tasks
-        .insert(training_task.id.clone(), training_task.clone());
-    let model_chunk = ModelChunk {
-        id: "chunk1".to_string(),
-        model_id: "model1".to_string(),
-        data: vec![0u8; 1024],
-    };
-    let result = task_manager.submit_training_results(&training_task.id, model_chunk.clone());
-    assert_eq!(result, Ok(model_chunk.id.clone()));
-    assert!(task_manager.model_chunks.contains_key(&model_chunk.id));
+    task_manager.create_training_task(training_task.clone(), 0).unwrap();
+    task_manager.claim_training_task("user1", 1).unwrap();
+    task_manager.start_training_task("user1", &training_task.id, 2).unwrap();
+    let too_many = MAX_NUM_SAMPLES + 1;
+    assert!(matches!(
+        task_manager.submit_training_results("user1", &training_task.id, vec![1; 8], too_many, 3),
+        Err(TaskManagerError::InvalidArgument { .. })
+    ));
+    let result = task_manager.submit_training_results("user1", &training_task.id, vec![1; 8], 4, 3);
+    assert_eq!(result, Ok(()));
+    let task = &task_manager.training_tasks[&training_task.id];
+    assert_eq!(task.status, TrainingTaskStatus::Submitted);
+    assert_eq!(task.model_weights, Some(vec![1; 8]));
+    assert_eq!(task.num_samples, 4);
 }
 
 #[test]
@@ -112,13 +212,23 @@ fn test_get_model_chunk() {
     let model_chunk = ModelChunk {
         id: "chunk1".to_string(),
         model_id: "model1".to_string(),
+        user_id: "user1".to_string(),
         data: vec![0u8; 1024],
+        index: 0,
+        total_chunks: 1,
+        replica: 0,
+        offset: 0,
+        content_hash: content_hash(&[0u8; 1024]),
+        lease_expires_at: None,
     };
     task_manager
         .model_chunks
         .insert(model_chunk.id.clone(), model_chunk.clone());
-    let result = task_manager.get_model_chunk(&model_chunk.id);
-    assert_eq!(result, Ok(model_chunk.clone()));
+    let chunks = task_manager.get_model_chunks("user1").unwrap();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].id, model_chunk.id);
+    assert_eq!(chunks[0].data, model_chunk.data);
+    assert!(task_manager.get_model_chunks("user2").unwrap().is_empty());
 }
 
 #[test]
@@ -129,12 +239,18 @@ fn test_get_training_task() {
         model_id: "model1".to_string(),
         training_data: vec![0u8; 1024],
         model_weights: None,
+        ..Default::default()
     };
     task_manager
         .training_tasks
         .insert(training_task.id.clone(), training_task.clone());
-    let result = task_manager.get_training_task(&training_task.id);
-    assert_eq!(result, Ok(training_task.clone()));
+    let result = task_manager.get_training_task(&training_task.id).unwrap();
+    assert_eq!(result.model_id, training_task.model_id);
+    assert_eq!(result.training_data, training_task.training_data);
+    assert!(matches!(
+        task_manager.get_training_task("task2"),
+        Err(TaskManagerError::TrainingTaskNotFound { .. })
+    ));
 }
 
 #[test]
@@ -145,10 +261,18 @@ fn test_get_user() {
         resources: 100,
         rewards: 0,
         rate_limit_tokens: 10,
+        owner: None,
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
     };
     task_manager.users.insert(user.id.clone(), user.clone());
-    let result = task_manager.get_user(&user.id);
-    assert_eq!(result, Ok(user.clone()));
+    let result = task_manager.get_user_ref(&user.id).unwrap();
+    assert_eq!(result.resources, user.resources);
+    assert_eq!(task_manager.get_rewards(&user.id), Ok(0));
+    assert!(matches!(
+        task_manager.get_user_ref("user2"),
+        Err(TaskManagerError::UserNotFound { .. })
+    ));
 }
 
 #[test]
@@ -158,8 +282,2020 @@ fn test_get_model() {
         id: "model1".to_string(),
         min_resources: 500,
         active: false,
+        version: "1.0.0".to_string(),
+        ..Default::default()
+    };
+    task_manager.models.insert(model.id.clone(), model.clone());
+    let result = task_manager.get_model(&model.id).unwrap();
+    assert_eq!(result.min_resources, model.min_resources);
+    assert_eq!(result.version, model.version);
+    assert!(matches!(
+        task_manager.get_model("model2"),
+        Err(TaskManagerError::ModelNotFound { .. })
+    ));
+}
+
+#[test]
+fn test_check_user_access() {
+    let mut task_manager = TaskManagerImpl::default();
+    let owner = Principal::from_slice(&[1]);
+    let other = Principal::from_slice(&[2]);
+    let user = User {
+        id: "user1".to_string(),
+        resources: 100,
+        rewards: 0,
+        rate_limit_tokens: 10,
+        owner: Some(owner),
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
+    };
+    task_manager.register_user(user.clone()).unwrap();
+    assert_eq!(task_manager.check_user_access(&owner, &user.id), Ok(()));
+    let denied = TaskManagerError::NotUserOwner {
+        user_id: user.id.clone(),
+    };
+    assert_eq!(task_manager.check_user_access(&other, &user.id), Err(denied));
+    assert!(task_manager.check_user_access(&owner, "missing").is_err());
+    assert_eq!(task_manager.check_registered_user(&owner), Ok(()));
+    assert!(task_manager.check_registered_user(&other).is_err());
+}
+
+#[test]
+fn test_register_user_one_per_principal() {
+    let mut task_manager = TaskManagerImpl::default();
+    let owner = Principal::from_slice(&[1]);
+    let mut user = User {
+        id: "user1".to_string(),
+        resources: 100,
+        rewards: 0,
+        rate_limit_tokens: 10,
+        owner: Some(owner),
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
+    };
+    task_manager.register_user(user.clone()).unwrap();
+    user.id = "user2".to_string();
+    assert!(task_manager.register_user(user).is_err());
+}
+
+#[test]
+fn test_admin_roles() {
+    let mut task_manager = TaskManagerImpl::default();
+    let controller = Principal::from_slice(&[1]);
+    let admin = Principal::from_slice(&[2]);
+    task_manager.init(controller);
+    assert_eq!(task_manager.check_controller_access(&controller), Ok(()));
+    assert_eq!(task_manager.check_admin_access(&controller), Ok(()));
+    assert!(task_manager.check_admin_access(&admin).is_err());
+
+    task_manager.add_admin(admin).unwrap();
+    assert_eq!(task_manager.check_admin_access(&admin), Ok(()));
+    assert!(task_manager.check_controller_access(&admin).is_err());
+
+    task_manager.remove_admin(&admin).unwrap();
+    assert!(task_manager.check_admin_access(&admin).is_err());
+    assert!(task_manager.remove_controller(&controller).is_err());
+}
+
+#[test]
+fn test_stable_state_round_trip() {
+    let mut task_manager = TaskManagerImpl::default();
+    let controller = Principal::from_slice(&[1]);
+    task_manager.init(controller);
+    let user = User {
+        id: "user1".to_string(),
+        resources: 100,
+        rewards: 7,
+        rate_limit_tokens: 10,
+        owner: Some(controller),
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
+    };
+    task_manager.register_user(user.clone()).unwrap();
+    // Registration zeroes declared rewards, so the earned balance is set directly.
+    task_manager.users.get_mut(&user.id).unwrap().rewards = 7;
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 100,
+        version: "1.0.0".to_string(),
+        ..Default::default()
+    };
+    task_manager.register_model(model.clone(), 0).unwrap();
+    task_manager.pledge_resources(&user.id, &model.id, 100).unwrap();
+    task_manager.activate_model(&model.id).unwrap();
+
+    let bytes = encode_state(&task_manager).unwrap();
+    let restored = decode_state(&bytes).unwrap();
+    assert_eq!(restored.get_rewards(&user.id), Ok(7));
+    assert_eq!(restored.check_user_access(&controller, &user.id), Ok(()));
+    assert_eq!(restored.check_controller_access(&controller), Ok(()));
+    assert!(restored.models.get(&model.id).unwrap().active);
+}
+
+#[test]
+fn test_stable_state_backfills_model_versions() {
+    // A model saved before versioning: no version string and no published record.
+    let mut task_manager = TaskManagerImpl::default();
+    let legacy = Model {
+        id: "model1".to_string(),
+        backend: InferenceBackend::Onnx,
+        active: true,
+        ..Default::default()
+    };
+    task_manager.models.insert(legacy.id.clone(), legacy);
+    task_manager.model_weights.insert("model1".to_string(), vec![1, 2, 3]);
+
+    let bytes = encode_state(&task_manager).unwrap();
+    let mut restored = decode_state(&bytes).unwrap();
+    let model = restored.get_model("model1").unwrap();
+    assert_eq!(model.version, LEGACY_MODEL_VERSION);
+    assert_eq!(model.weight_hash, content_hash(&[1, 2, 3]));
+    let versions = restored.get_model_versions("model1").unwrap();
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0].model.version, LEGACY_MODEL_VERSION);
+
+    // The restored model can be promoted away from and rolled back to.
+    let next = Model {
+        id: "model1".to_string(),
+        backend: InferenceBackend::Onnx,
+        version: "1.0.0".to_string(),
+        ..Default::default()
+    };
+    restored.register_model(next, 10).unwrap();
+    restored
+        .upload_model_version_weights("model1", "1.0.0", vec![4, 5, 6])
+        .unwrap();
+    restored.promote_model_version("model1", "1.0.0", 20).unwrap();
+    assert_eq!(restored.get_model("model1").unwrap().version, "1.0.0");
+    assert_eq!(restored.rollback_model("model1", 30), Ok(LEGACY_MODEL_VERSION.to_string()));
+    assert_eq!(restored.model_weights.get("model1"), Some(&vec![1, 2, 3]));
+}
+
+#[test]
+fn test_stable_state_blob_encoding() {
+    let mut task_manager = TaskManagerImpl::default();
+    let weights: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+    task_manager.model_weights.insert("model1".to_string(), weights.clone());
+    let task = TrainingTask {
+        id: "task1".to_string(),
+        model_id: "model1".to_string(),
+        training_data: vec![7; 1_000],
+        model_weights: Some(vec![9; 1_000]),
+        ..Default::default()
+    };
+    task_manager.training_tasks.insert(task.id.clone(), task);
+
+    // Blobs are byte strings, so the state is barely larger than the bytes it holds.
+    let bytes = encode_state(&task_manager).unwrap();
+    assert!(bytes.len() < 103_000);
+    let restored = decode_state(&bytes).unwrap();
+    assert_eq!(restored.model_weights["model1"], weights);
+    assert_eq!(restored.training_tasks["task1"].model_weights, Some(vec![9; 1_000]));
+
+    // Version 1 stored blobs as arrays of integers; those still decode.
+    fn as_integers(value: serde_cbor::Value) -> serde_cbor::Value {
+        use serde_cbor::Value;
+        match value {
+            Value::Bytes(bytes) => {
+                Value::Array(bytes.into_iter().map(|byte| Value::Integer(byte.into())).collect())
+            }
+            Value::Array(values) => Value::Array(values.into_iter().map(as_integers).collect()),
+            Value::Map(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, as_integers(value)))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+    let value: serde_cbor::Value = serde_cbor::from_slice(&bytes[12..]).unwrap();
+    let payload = serde_cbor::to_vec(&as_integers(value)).unwrap();
+    let mut legacy = 1u32.to_le_bytes().to_vec();
+    legacy.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    legacy.extend_from_slice(&payload);
+    assert!(legacy.len() > bytes.len() + 50_000);
+    let restored = decode_state(&legacy).unwrap();
+    assert_eq!(restored.model_weights["model1"], weights);
+    assert_eq!(restored.training_tasks["task1"].training_data, vec![7; 1_000]);
+}
+
+#[test]
+fn test_stable_state_rejects_unknown_version() {
+    let mut bytes = encode_state(&TaskManagerImpl::default()).unwrap();
+    bytes[0..4].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+    assert!(decode_state(&bytes).is_err());
+    assert!(decode_state(&bytes[..4]).is_err());
+}
+
+#[test]
+fn test_distribute_model_chunks() {
+    let mut task_manager = TaskManagerImpl::default();
+    for (id, resources) in [("user1", 300), ("user2", 100), ("user3", 0), ("user4", 1_000)] {
+        let user = User {
+            id: id.to_string(),
+            resources,
+            rewards: 0,
+            rate_limit_tokens: 10,
+            owner: None,
+            tier: RateLimitTier::Free,
+            rate_limit_refilled_at: 0,
+        };
+        task_manager.users.insert(user.id.clone(), user);
+    }
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 500,
+        active: false,
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
+    };
+    task_manager.models.insert(model.id.clone(), model.clone());
+    assert!(task_manager.distribute_model_chunks(&model.id, 2, 0).is_err());
+    // Shards follow each user's pledge to the model, not their total resources.
+    let other = Model {
+        id: "model2".to_string(),
+        ..model.clone()
+    };
+    task_manager.models.insert(other.id.clone(), other);
+    task_manager.pledge_resources("user1", &model.id, 300).unwrap();
+    task_manager.pledge_resources("user2", &model.id, 100).unwrap();
+    task_manager.pledge_resources("user4", "model2", 1_000).unwrap();
+    let config = VerificationConfig {
+        redundancy: 1,
+        tolerance: 0.0,
+    };
+    task_manager.set_verification_config(config).unwrap();
+
+    let weights: Vec<u8> = (0..16).collect();
+    task_manager.upload_model_weights(&model.id, weights.clone()).unwrap();
+    let result = task_manager.distribute_model_chunks(&model.id, 2, 0);
+    assert_eq!(result, Ok(()));
+    assert_eq!(task_manager.model_chunks.len(), 8);
+    assert_eq!(task_manager.get_model_chunks("user1").unwrap().len(), 6);
+    assert_eq!(task_manager.get_model_chunks("user2").unwrap().len(), 2);
+    assert!(task_manager.get_model_chunks("user3").unwrap().is_empty());
+    assert!(task_manager.get_model_chunks("user4").unwrap().is_empty());
+    let chunk = task_manager.model_chunks.get("model1:3:0").unwrap();
+    assert_eq!(chunk.data, weights[6..8].to_vec());
+
+    // Redistributing replaces the previous shards rather than adding to them.
+    task_manager.distribute_model_chunks(&model.id, 4, 0).unwrap();
+    assert_eq!(task_manager.model_chunks.len(), 4);
+
+    // Sizes beyond the weights, even past `u32::MAX`, give a single shard.
+    for chunk_size in [17, 1 << 32, u64::MAX] {
+        task_manager.distribute_model_chunks(&model.id, chunk_size, 0).unwrap();
+        assert_eq!(task_manager.model_chunks.len(), 1);
+        assert_eq!(task_manager.model_chunks["model1:0:0"].data, weights);
+    }
+    assert!(matches!(
+        task_manager.distribute_model_chunks(&model.id, 0, 0),
+        Err(TaskManagerError::InvalidArgument { .. })
+    ));
+}
+
+#[test]
+fn test_reassemble_model() {
+    let mut task_manager = TaskManagerImpl::default();
+    let user = User {
+        id: "user1".to_string(),
+        resources: 100,
+        rewards: 0,
+        rate_limit_tokens: 10,
+        owner: None,
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
+    };
+    task_manager.users.insert(user.id.clone(), user);
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 500,
+        active: false,
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
+    };
+    task_manager.models.insert(model.id.clone(), model.clone());
+    let config = VerificationConfig {
+        redundancy: 1,
+        tolerance: 0.0,
+    };
+    task_manager.set_verification_config(config).unwrap();
+    let weights: Vec<u8> = (0..10).collect();
+    task_manager.upload_model_weights(&model.id, weights.clone()).unwrap();
+    task_manager.pledge_resources("user1", &model.id, 100).unwrap();
+    task_manager.distribute_model_chunks(&model.id, 3, 0).unwrap();
+
+    let chunks = task_manager.get_model_chunks("user1").unwrap();
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks[3].offset, 9);
+    assert_eq!(chunks[3].total_chunks, 4);
+    assert_eq!(task_manager.get_reassembled_model(&model.id), Ok(weights.clone()));
+
+    task_manager.model_chunks.get_mut("model1:1:0").unwrap().data[0] ^= 0xff;
+    assert!(task_manager.get_reassembled_model(&model.id).is_err());
+
+    task_manager.distribute_model_chunks(&model.id, 3, 0).unwrap();
+    task_manager.model_chunks.remove("model1:2:0");
+    let result = task_manager.reassemble_model(&model.id);
+    let expected = TaskManagerError::IncompleteModel {
+        model_id: model.id.clone(),
+        present: 3,
+        expected: 4,
+    };
+    assert_eq!(result, Err(expected));
+}
+
+#[test]
+fn test_chunk_lease_renew_and_reclaim() {
+    let mut task_manager = TaskManagerImpl::default();
+    for (id, resources) in [("user1", 100), ("user2", 100)] {
+        let user = User {
+            id: id.to_string(),
+            resources,
+            rewards: 0,
+            rate_limit_tokens: 10,
+            owner: None,
+            tier: RateLimitTier::Free,
+            rate_limit_refilled_at: 0,
+        };
+        task_manager.users.insert(user.id.clone(), user);
+    }
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 500,
+        active: false,
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
     };
     task_manager.models.insert(model.id.clone(), model.clone());
-    let result = task_manager.get_model(&model.id);
-    assert_eq!(result, Ok(model.clone()));
+    let config = VerificationConfig {
+        redundancy: 1,
+        tolerance: 0.0,
+    };
+    task_manager.set_verification_config(config).unwrap();
+    task_manager.upload_model_weights(&model.id, vec![0u8; 4]).unwrap();
+    for user_id in ["user1", "user2"] {
+        task_manager.pledge_resources(user_id, &model.id, 100).unwrap();
+    }
+    task_manager.distribute_model_chunks(&model.id, 2, 0).unwrap();
+    let chunk = task_manager.get_model_chunks("user1").unwrap()[0].clone();
+
+    // A heartbeat halfway through the lease pushes the expiry out.
+    let now = CHUNK_LEASE_DURATION_NS / 2;
+    let expires_at = task_manager.renew_chunk_lease("user1", &chunk.id, now).unwrap();
+    assert_eq!(expires_at, now + CHUNK_LEASE_DURATION_NS);
+    assert!(task_manager.renew_chunk_lease("user2", &chunk.id, now).is_err());
+
+    // user2 never renewed, so its chunk moves to user1 once its lease runs out.
+    let now = CHUNK_LEASE_DURATION_NS;
+    let reassigned = task_manager.reclaim_expired_leases(now);
+    assert_eq!(reassigned, vec!["model1:1:0".to_string()]);
+    assert_eq!(task_manager.get_model_chunks("user1").unwrap().len(), 2);
+    assert!(task_manager.get_model_chunks("user2").unwrap().is_empty());
+
+    // Submitting after the lease has expired is rejected.
+    let late = expires_at + 1;
+    assert!(task_manager.submit_computed_chunk(chunk, vec![], late).is_err());
+}
+
+#[test]
+fn test_submit_computed_chunk_majority_verification() {
+    let mut task_manager = TaskManagerImpl::default();
+    for id in ["user1", "user2", "user3"] {
+        let user = User {
+            id: id.to_string(),
+            resources: 100,
+            rewards: 0,
+            rate_limit_tokens: 10,
+            owner: None,
+            tier: RateLimitTier::Free,
+            rate_limit_refilled_at: 0,
+        };
+        task_manager.users.insert(user.id.clone(), user);
+    }
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 500,
+        active: false,
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
+    };
+    task_manager.models.insert(model.id.clone(), model.clone());
+    let config = VerificationConfig {
+        redundancy: 3,
+        tolerance: 0.01,
+    };
+    task_manager.set_verification_config(config).unwrap();
+    task_manager.upload_model_weights(&model.id, vec![0u8; 4]).unwrap();
+    for user_id in ["user1", "user2", "user3"] {
+        task_manager.pledge_resources(user_id, &model.id, 100).unwrap();
+    }
+    task_manager.distribute_model_chunks(&model.id, 4, 0).unwrap();
+
+    let replica = |task_manager: &TaskManagerImpl, user_id: &str| {
+        task_manager.get_model_chunks(user_id).unwrap()[0].clone()
+    };
+    let good = vec![1.0f32.to_bits(), 2.0f32.to_bits()];
+    let good_results = good.clone();
+    let close = vec![1.001f32.to_bits(), 2.0f32.to_bits()];
+    let wrong = vec![5.0f32.to_bits(), 2.0f32.to_bits()];
+
+    let chunk = replica(&task_manager, "user1");
+    task_manager.submit_computed_chunk(chunk, good, 1).unwrap();
+    let chunk = replica(&task_manager, "user2");
+    task_manager.submit_computed_chunk(chunk, wrong, 1).unwrap();
+    assert_eq!(task_manager.get_rewards("user1"), Ok(0));
+    // Pending results are hidden from other holders; only hashes and submitters show.
+    let pending = task_manager.get_shard_verification("model1:0").unwrap().redacted();
+    assert_eq!(pending.submissions.len(), 2);
+    assert!(pending.submissions.iter().all(|submission| submission.results.is_empty()));
+    assert!(!pending.submissions[0].result_hash.is_empty());
+
+    // The third result is within tolerance of the first, forming a 2-of-3 majority.
+    let chunk = replica(&task_manager, "user3");
+    task_manager.submit_computed_chunk(chunk.clone(), close, 1).unwrap();
+    assert!(task_manager.submit_computed_chunk(chunk, vec![], 1).is_err());
+    assert_eq!(task_manager.get_rewards("user1"), Ok(1));
+    assert_eq!(task_manager.get_rewards("user2"), Ok(0));
+    assert_eq!(task_manager.get_rewards("user3"), Ok(1));
+    let verification = task_manager.get_shard_verification("model1:0").unwrap().redacted();
+    assert_eq!(verification.status, VerificationStatus::Accepted);
+    assert_eq!(verification.submissions[0].results, good_results);
+}
+
+#[test]
+fn test_reputation_and_slashing() {
+    let mut task_manager = TaskManagerImpl::default();
+    for id in ["user1", "user2", "user3"] {
+        let user = User {
+            id: id.to_string(),
+            resources: 100,
+            rewards: 20,
+            rate_limit_tokens: 10,
+            owner: None,
+            tier: RateLimitTier::Free,
+            rate_limit_refilled_at: 0,
+        };
+        task_manager.users.insert(user.id.clone(), user);
+    }
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 500,
+        active: false,
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
+    };
+    task_manager.models.insert(model.id.clone(), model.clone());
+    task_manager.upload_model_weights(&model.id, vec![0u8; 8]).unwrap();
+    for user_id in ["user1", "user2", "user3"] {
+        task_manager.pledge_resources(user_id, &model.id, 100).unwrap();
+    }
+    task_manager.distribute_model_chunks(&model.id, 4, 0).unwrap();
+
+    for (user_id, results) in [("user1", vec![1]), ("user2", vec![2]), ("user3", vec![1])] {
+        let chunk = task_manager.get_model_chunks(user_id).unwrap()[0].clone();
+        task_manager.submit_computed_chunk(chunk, results, 1).unwrap();
+    }
+    assert_eq!(task_manager.get_rewards("user1"), Ok(21));
+    assert_eq!(task_manager.get_rewards("user2"), Ok(18));
+    let good = task_manager.get_reputation("user1").unwrap();
+    let bad = task_manager.get_reputation("user2").unwrap();
+    assert_eq!(good.accepted, 1);
+    assert_eq!(bad.rejected, 1);
+    assert!(good.score > bad.score);
+
+    // Nobody submits the second shard; every holder times out.
+    task_manager.reclaim_expired_leases(CHUNK_LEASE_DURATION_NS);
+    assert_eq!(task_manager.get_reputation("user3").unwrap().timed_out, 1);
+
+    // The lower-reputation user is now last in line for new work.
+    let config = VerificationConfig {
+        redundancy: 1,
+        tolerance: 0.0,
+    };
+    task_manager.set_verification_config(config).unwrap();
+    task_manager.distribute_model_chunks(&model.id, 4, 0).unwrap();
+    assert!(task_manager.get_model_chunks("user2").unwrap().is_empty());
+}
+
+#[test]
+fn test_rate_limit_token_bucket() {
+    let mut task_manager = TaskManagerImpl::default();
+    let owner = Principal::from_slice(&[1]);
+    let user = User {
+        id: "user1".to_string(),
+        resources: 0,
+        rewards: 0,
+        rate_limit_tokens: 1_000,
+        owner: Some(owner),
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
+    };
+    task_manager.register_user(user.clone()).unwrap();
+    assert_eq!(task_manager.find_user_id(&owner), Ok(user.id.clone()));
+
+    // Registration cannot grant more than the tier's capacity.
+    let limits = RateLimitConfig::default().free;
+    let now = 1_000 * limits.refill_interval_ns;
+    for _ in 0..limits.capacity {
+        assert!(task_manager.consume_rate_limit_token(&user.id, now).is_ok());
+    }
+    let throttled = task_manager.consume_rate_limit_token(&user.id, now);
+    assert!(matches!(throttled, Err(TaskManagerError::RateLimited { .. })));
+
+    // One refill interval later exactly one more call goes through.
+    let now = now + limits.refill_interval_ns;
+    assert_eq!(task_manager.consume_rate_limit_token(&user.id, now), Ok(0));
+    assert!(task_manager.consume_rate_limit_token(&user.id, now).is_err());
+
+    // Upgrading the tier raises the ceiling the bucket refills to.
+    task_manager.set_user_tier(&user.id, RateLimitTier::Premium).unwrap();
+    let now = now + 1_000 * limits.refill_interval_ns;
+    let remaining = task_manager.consume_rate_limit_token(&user.id, now).unwrap();
+    assert_eq!(remaining, RateLimitConfig::default().premium.capacity - 1);
+}
+
+// Echoes the prompt back so tests can run without a real model.
+struct EchoGenerator;
+
+impl TextGenerator for EchoGenerator {
+    fn generate(&self, request: &CompletionRequest) -> Result<Generation, TaskManagerError> {
+        Ok(Generation {
+            text: request.prompt.to_uppercase(),
+            prompt_tokens: request.prompt.split_whitespace().count() as u32,
+            completion_tokens: request.max_new_tokens,
+            token_logprobs: request
+                .logprobs
+                .then(|| vec![-0.5; request.max_new_tokens as usize]),
+            finish_reason: FinishReason::Length,
+        })
+    }
+}
+
+fn completion_request(prompt: &str) -> CompletionRequest {
+    CompletionRequest {
+        prompt: prompt.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_generate_completion() {
+    let mut task_manager = TaskManagerImpl::default();
+    assert_eq!(
+        task_manager
+            .generate_completion("user1", &completion_request("hello"), 0)
+            .err(),
+        Some(TaskManagerError::NoActiveModels)
+    );
+
+    for (id, active) in [("model2", true), ("model1", true), ("model3", false)] {
+        let model = Model {
+            id: id.to_string(),
+            min_resources: 0,
+            active,
+            backend: InferenceBackend::Onnx,
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+        task_manager.register_model(model, 0).unwrap();
+        if active {
+            task_manager.activate_model(id).unwrap();
+        }
+    }
+    assert_eq!(
+        task_manager
+            .generate_completion("user1", &completion_request("hello"), 0)
+            .err(),
+        Some(TaskManagerError::InferenceBackendNotLoaded {
+            model_id: "model1".to_string()
+        })
+    );
+    assert_eq!(
+        task_manager.load_inference_backend("model1").err(),
+        Some(TaskManagerError::ModelWeightsNotFound {
+            model_id: "model1".to_string()
+        })
+    );
+
+    task_manager.set_text_generator("model1", Box::new(EchoGenerator));
+    let request = CompletionRequest {
+        max_new_tokens: 3,
+        logprobs: true,
+        ..completion_request("hello world")
+    };
+    let completion = task_manager.generate_completion("user1", &request, 42).unwrap();
+    assert_eq!(completion.model_id, "model1");
+    assert_eq!(completion.user_id, "user1");
+    assert_eq!(completion.prompt, "hello world");
+    assert_eq!(completion.generated_text, "HELLO WORLD");
+    assert_eq!(completion.prompt_tokens, 2);
+    assert_eq!(completion.completion_tokens, 3);
+    assert_eq!(completion.token_logprobs, Some(vec![-0.5; 3]));
+    assert_eq!(completion.finish_reason, FinishReason::Length);
+    assert_eq!(completion.created_at, 42);
+
+    let stored = task_manager.get_completion(&completion.id).unwrap();
+    assert_eq!(stored.generated_text, completion.generated_text);
+    let second = task_manager
+        .generate_completion("user1", &completion_request("again"), 43)
+        .unwrap();
+    assert_ne!(second.id, completion.id);
+    assert_eq!(second.token_logprobs, None);
+    assert_eq!(
+        task_manager.get_completion("cmpl-99").err(),
+        Some(TaskManagerError::CompletionNotFound {
+            completion_id: "cmpl-99".to_string()
+        })
+    );
+
+    let request = CompletionRequest {
+        model_id: Some("model3".to_string()),
+        ..completion_request("hello")
+    };
+    assert_eq!(
+        task_manager.generate_completion("user1", &request, 0).err(),
+        Some(TaskManagerError::ModelNotActive {
+            model_id: "model3".to_string()
+        })
+    );
+}
+
+// Word-level `tokenizer.json`, small enough to inline in a test.
+const TEST_TOKENIZER: &str = r#"{
+    "version": "1.0",
+    "truncation": null,
+    "padding": null,
+    "added_tokens": [],
+    "normalizer": null,
+    "pre_tokenizer": { "type": "Whitespace" },
+    "post_processor": null,
+    "decoder": null,
+    "model": {
+        "type": "WordLevel",
+        "vocab": { "hello": 0, "world": 1, "[UNK]": 2 },
+        "unk_token": "[UNK]"
+    }
+}"#;
+
+#[test]
+fn test_onnx_tokenizer_and_logits() {
+    let mut task_manager = TaskManagerImpl::default();
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 0,
+        backend: InferenceBackend::Onnx,
+        version: "1.0.0".to_string(),
+        ..Default::default()
+    };
+    task_manager.register_model(model, 0).unwrap();
+    task_manager.activate_model("model1").unwrap();
+    task_manager.upload_model_weights("model1", vec![0u8; 4]).unwrap();
+    // The ONNX backend needs the model's tokenizer and never reads one from disk.
+    assert!(matches!(
+        task_manager.load_inference_backend("model1"),
+        Err(TaskManagerError::InferenceFailed(_))
+    ));
+    assert!(matches!(
+        task_manager.upload_model_tokenizer("model1", b"not json".to_vec()),
+        Err(TaskManagerError::InferenceFailed(_))
+    ));
+    let tokenizer_json = TEST_TOKENIZER.as_bytes().to_vec();
+    task_manager.upload_model_tokenizer("model1", tokenizer_json.clone()).unwrap();
+    assert!(matches!(
+        task_manager.upload_model_tokenizer("model2", tokenizer_json.clone()),
+        Err(TaskManagerError::ModelNotFound { .. })
+    ));
+    let tokenizer = GPT2Tokenizer::from_bytes(&tokenizer_json).unwrap();
+    assert_eq!(tokenizer.vocab_size(), 3);
+    assert_eq!(tokenizer.encode("hello world").unwrap(), vec![0, 1]);
+    assert_eq!(tokenizer.decode(&[1, 0]).unwrap(), "world hello");
+
+    // Graphs may export every position's logits or only the last position's.
+    let all_positions = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+    assert_eq!(last_position_logits(all_positions, 2, 3), Ok(vec![4.0, 5.0, 6.0]));
+    let last_position = vec![1.0, 2.0, 3.0, 4.0];
+    assert_eq!(last_position_logits(last_position.clone(), 2, 3), Ok(last_position));
+    assert!(last_position_logits(vec![1.0, 2.0], 3, 3).is_err());
+    assert!(last_position_logits(Vec::new(), 1, 0).is_err());
+}
+
+#[test]
+fn test_completion_request_validation() {
+    assert!(completion_request("hello").validate().is_ok());
+    let invalid = [
+        completion_request(""),
+        CompletionRequest {
+            max_new_tokens: 0,
+            ..completion_request("hello")
+        },
+        CompletionRequest {
+            temperature: -1.0,
+            ..completion_request("hello")
+        },
+        CompletionRequest {
+            top_p: 0.0,
+            ..completion_request("hello")
+        },
+        CompletionRequest {
+            repetition_penalty: f32::NAN,
+            ..completion_request("hello")
+        },
+        CompletionRequest {
+            stop_sequences: vec![String::new()],
+            ..completion_request("hello")
+        },
+    ];
+    for request in invalid {
+        assert!(matches!(
+            request.validate(),
+            Err(TaskManagerError::InvalidArgument { .. })
+        ));
+    }
+}
+
+#[test]
+fn test_sample_token() {
+    let logits = [1.0, 4.0, 3.0, 0.5];
+    let greedy = CompletionRequest {
+        temperature: 0.0,
+        ..completion_request("hello")
+    };
+    let mut rng = SamplingRng::new(0);
+    assert_eq!(sample_token(&logits, &[], &greedy, &mut rng), 1);
+
+    // A strong repetition penalty pushes greedy decoding off the already generated token.
+    let penalized = CompletionRequest {
+        repetition_penalty: 2.0,
+        ..greedy.clone()
+    };
+    assert_eq!(sample_token(&logits, &[1, 1], &penalized, &mut rng), 2);
+
+    // Top-k of one always keeps only the best candidate.
+    let top_k = CompletionRequest {
+        top_k: 1,
+        ..completion_request("hello")
+    };
+    assert_eq!(sample_token(&logits, &[], &top_k, &mut rng), 1);
+
+    let logprob = log_probability(&logits, 1);
+    assert!(logprob < 0.0 && logprob > log_probability(&logits, 2));
+
+    // The same seed reproduces the same sequence of samples.
+    let sampled = completion_request("hello");
+    let draw = |seed| {
+        let mut rng = SamplingRng::new(seed);
+        (0..8)
+            .map(|_| sample_token(&logits, &[], &sampled, &mut rng))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(draw(7), draw(7));
+}
+
+#[test]
+fn test_completion_jobs() {
+    let mut task_manager = TaskManagerImpl::default();
+    for id in ["client", "worker1", "worker2", "idle"] {
+        let user = User {
+            id: id.to_string(),
+            resources: 100,
+            rewards: 0,
+            rate_limit_tokens: 10,
+            owner: None,
+            tier: RateLimitTier::Free,
+            rate_limit_refilled_at: 0,
+        };
+        task_manager.register_user(user).unwrap();
+    }
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 0,
+        version: "1.0.0".to_string(),
+        ..Default::default()
+    };
+    task_manager.register_model(model, 0).unwrap();
+    task_manager.activate_model("model1").unwrap();
+    for worker in ["client", "worker1", "worker2"] {
+        let chunk = ModelChunk {
+            id: format!("model1:0:{}", worker),
+            model_id: "model1".to_string(),
+            user_id: worker.to_string(),
+            data: vec![1, 2, 3],
+            index: 0,
+            total_chunks: 1,
+            replica: 0,
+            offset: 0,
+            content_hash: Vec::new(),
+            lease_expires_at: None,
+        };
+        task_manager.model_chunks.insert(chunk.id.clone(), chunk);
+    }
+
+    let request = CompletionRequest {
+        max_new_tokens: 4,
+        stop_sequences: vec!["\n".to_string()],
+        ..completion_request("hello")
+    };
+    let job_id = task_manager.submit_completion_job("client", request, 10).unwrap();
+    let job = task_manager.get_completion_job(&job_id).unwrap();
+    assert_eq!(job.status, CompletionJobStatus::Queued);
+    assert_eq!(job.model_id, "model1");
+
+    // Users without a shard of the model are never handed the job, nor is its submitter.
+    assert!(task_manager.claim_completion_job("idle", 20).unwrap().is_none());
+    assert!(task_manager.claim_completion_job("client", 20).unwrap().is_none());
+    let claimed = task_manager.claim_completion_job("worker1", 20).unwrap().unwrap();
+    assert_eq!(claimed.id, job_id);
+    assert!(task_manager.claim_completion_job("worker2", 20).unwrap().is_none());
+
+    let partial = CompletionJobUpdate {
+        text: " wor".to_string(),
+        tokens: 1,
+        token_logprobs: Vec::new(),
+        prompt_tokens: 0,
+        finish_reason: None,
+        error: None,
+    };
+    assert_eq!(
+        task_manager.post_completion_job_update("worker1", &job_id, partial.clone(), 30),
+        Ok(CompletionJobStatus::Running)
+    );
+
+    // Once the lease lapses the job moves to another worker and earlier output is dropped.
+    let expired = 30 + COMPLETION_JOB_LEASE_DURATION_NS;
+    let reclaimed = task_manager
+        .claim_completion_job("worker2", expired)
+        .unwrap()
+        .unwrap();
+    assert_eq!(reclaimed.worker_id.as_deref(), Some("worker2"));
+    assert_eq!(reclaimed.output, "");
+    assert_eq!(
+        task_manager.post_completion_job_update("worker1", &job_id, partial.clone(), expired),
+        Err(TaskManagerError::CompletionJobNotAssigned {
+            job_id: job_id.clone(),
+            user_id: "worker1".to_string()
+        })
+    );
+
+    let too_long = CompletionJobUpdate {
+        tokens: 5,
+        ..partial.clone()
+    };
+    assert!(task_manager
+        .post_completion_job_update("worker2", &job_id, too_long, expired)
+        .is_err());
+    // Text needs tokens to account for it, within a bounded size per token.
+    let no_tokens = CompletionJobUpdate {
+        tokens: 0,
+        ..partial.clone()
+    };
+    assert!(task_manager
+        .post_completion_job_update("worker2", &job_id, no_tokens, expired)
+        .is_err());
+    let oversized = CompletionJobUpdate {
+        text: "x".repeat(MAX_COMPLETION_BYTES_PER_TOKEN + 1),
+        ..partial.clone()
+    };
+    assert!(task_manager
+        .post_completion_job_update("worker2", &job_id, oversized, expired)
+        .is_err());
+
+    let last = CompletionJobUpdate {
+        text: " world\nmore".to_string(),
+        tokens: 3,
+        prompt_tokens: 1,
+        finish_reason: Some(FinishReason::Length),
+        ..partial
+    };
+    assert_eq!(
+        task_manager.post_completion_job_update("worker2", &job_id, last, expired + 1),
+        Ok(CompletionJobStatus::Completed)
+    );
+    let job = task_manager.get_completion_job(&job_id).unwrap();
+    let completion = task_manager
+        .get_completion(job.completion_id.as_deref().unwrap())
+        .unwrap();
+    assert_eq!(completion.generated_text, " world");
+    assert_eq!(completion.finish_reason, FinishReason::StopSequence);
+    assert_eq!(completion.user_id, "client");
+    assert_eq!(completion.completion_tokens, 3);
+
+    // The worker is only paid once the output has been reviewed, and only once.
+    assert_eq!(task_manager.get_rewards("worker2"), Ok(0));
+    task_manager.review_completion_job(&job_id, true, expired + 2).unwrap();
+    assert_eq!(task_manager.get_completion_job(&job_id).unwrap().verified, Some(true));
+    assert_eq!(task_manager.get_rewards("worker2"), Ok(1));
+    assert!(task_manager.review_completion_job(&job_id, false, expired + 3).is_err());
+    assert_eq!(task_manager.get_rewards("worker1"), Ok(0));
+
+    // Jobs nobody finishes fail once they expire instead of staying queued forever.
+    let stale_id = task_manager
+        .submit_completion_job("client", completion_request("stale"), expired)
+        .unwrap();
+    assert!(task_manager.review_completion_job(&stale_id, true, expired).is_err());
+    let later = expired + COMPLETION_JOB_EXPIRY_NS;
+    assert!(task_manager.claim_completion_job("worker1", later).unwrap().is_none());
+    let stale = task_manager.get_completion_job(&stale_id).unwrap();
+    assert_eq!(stale.status, CompletionJobStatus::Failed);
+    assert!(stale.error.is_some());
+
+    // The number of unfinished jobs is bounded.
+    for _ in 0..MAX_PENDING_COMPLETION_JOBS {
+        task_manager
+            .submit_completion_job("client", completion_request("hi"), later)
+            .unwrap();
+    }
+    assert_eq!(
+        task_manager.submit_completion_job("client", completion_request("hi"), later),
+        Err(TaskManagerError::CompletionQueueFull)
+    );
+}
+
+#[test]
+fn test_model_registry_metadata() {
+    let mut task_manager = TaskManagerImpl::default();
+    let models = [
+        ("neo-125m", ModelArchitecture::GptNeo, InferenceBackend::GptNeo),
+        ("gpt2-small", ModelArchitecture::Gpt2, InferenceBackend::Onnx),
+        ("bert-base", ModelArchitecture::Bert, InferenceBackend::Onnx),
+        ("gpt2-medium", ModelArchitecture::Gpt2, InferenceBackend::Onnx),
+    ];
+    for (id, architecture, backend) in models {
+        let model = Model {
+            id: id.to_string(),
+            backend,
+            architecture,
+            parameter_count: 125_000_000,
+            num_layers: 12,
+            num_heads: 12,
+            embedding_dim: 768,
+            tokenizer: "gpt2".to_string(),
+            weight_hash: vec![9; 32],
+            version: "1.2.0-beta.1".to_string(),
+            license: "MIT".to_string(),
+            ..Default::default()
+        };
+        task_manager.register_model(model, 0).unwrap();
+    }
+
+    let model = task_manager.get_model("neo-125m").unwrap();
+    assert_eq!(model.parameter_count, 125_000_000);
+    assert_eq!(model.tokenizer, "gpt2");
+    assert!(model.weight_hash.is_empty());
+    // GPT-Neo runs its pretrained weights, so only the ONNX models take uploads.
+    assert!(matches!(
+        task_manager.upload_model_weights("neo-125m", vec![1, 2, 3]),
+        Err(TaskManagerError::InvalidArgument { .. })
+    ));
+    task_manager.upload_model_weights("gpt2-small", vec![1, 2, 3]).unwrap();
+    assert_eq!(
+        task_manager.get_model("gpt2-small").unwrap().weight_hash,
+        content_hash(&[1, 2, 3])
+    );
+
+    let query = ModelQuery {
+        limit: 1,
+        ..Default::default()
+    };
+    let first = task_manager
+        .get_models_by_architecture(ModelArchitecture::Gpt2, &query)
+        .unwrap();
+    assert_eq!(first.total, 2);
+    assert_eq!(first.models[0].id, "gpt2-medium");
+    let query = ModelQuery {
+        cursor: first.next_cursor,
+        ..query
+    };
+    let second = task_manager
+        .get_models_by_architecture(ModelArchitecture::Gpt2, &query)
+        .unwrap();
+    assert_eq!(second.models[0].id, "gpt2-small");
+    assert_eq!(second.next_cursor, None);
+
+    for (version, backend, architecture) in [
+        ("1.0", InferenceBackend::Onnx, ModelArchitecture::Gpt2),
+        ("01.0.0", InferenceBackend::Onnx, ModelArchitecture::Gpt2),
+        ("1.0.0", InferenceBackend::GptNeo, ModelArchitecture::Bert),
+    ] {
+        let model = Model {
+            id: "invalid".to_string(),
+            backend,
+            architecture,
+            version: version.to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            task_manager.register_model(model, 0),
+            Err(TaskManagerError::InvalidArgument { .. })
+        ));
+    }
+    assert_eq!(
+        task_manager.get_model("invalid").err(),
+        Some(TaskManagerError::ModelNotFound {
+            model_id: "invalid".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_model_version_promotion_and_rollback() {
+    let mut task_manager = TaskManagerImpl::default();
+    for id in ["user1", "user2"] {
+        let user = User {
+            id: id.to_string(),
+            resources: 100,
+            rewards: 0,
+            rate_limit_tokens: 10,
+            owner: None,
+            tier: RateLimitTier::Free,
+            rate_limit_refilled_at: 0,
+        };
+        task_manager.users.insert(user.id.clone(), user);
+    }
+    let config = VerificationConfig {
+        redundancy: 1,
+        tolerance: 0.0,
+    };
+    task_manager.set_verification_config(config).unwrap();
+    let v1 = Model {
+        id: "model1".to_string(),
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
+    };
+    task_manager.register_model(v1.clone(), 0).unwrap();
+    task_manager.activate_model("model1").unwrap();
+    let weights_v1: Vec<u8> = (0..8).collect();
+    task_manager.upload_model_weights("model1", weights_v1.clone()).unwrap();
+    for user_id in ["user1", "user2"] {
+        task_manager.pledge_resources(user_id, "model1", 100).unwrap();
+    }
+    task_manager.distribute_model_chunks("model1", 4, 0).unwrap();
+    let holders = |task_manager: &TaskManagerImpl| {
+        let mut holders: Vec<(String, String)> = task_manager
+            .model_chunks
+            .values()
+            .map(|chunk| (chunk.id.clone(), chunk.user_id.clone()))
+            .collect();
+        holders.sort();
+        holders
+    };
+    let assignment = holders(&task_manager);
+
+    // Publishing a new version leaves the current one live.
+    let v2 = Model {
+        version: "1.1.0".to_string(),
+        min_resources: 10,
+        ..v1.clone()
+    };
+    task_manager.register_model(v2.clone(), 5).unwrap();
+    assert_eq!(
+        task_manager.register_model(v2, 6),
+        Err(TaskManagerError::ModelVersionAlreadyExists {
+            model_id: "model1".to_string(),
+            version: "1.1.0".to_string()
+        })
+    );
+    assert_eq!(task_manager.get_model("model1").unwrap().version, "1.0.0");
+    assert_eq!(
+        task_manager.promote_model_version("model1", "1.1.0", 10),
+        Err(TaskManagerError::ModelWeightsNotFound {
+            model_id: "model1".to_string()
+        })
+    );
+
+    let weights_v2: Vec<u8> = (100..112).collect();
+    task_manager
+        .upload_model_version_weights("model1", "1.1.0", weights_v2.clone())
+        .unwrap();
+    task_manager.promote_model_version("model1", "1.1.0", 10).unwrap();
+    let model = task_manager.get_model("model1").unwrap();
+    assert_eq!(model.version, "1.1.0");
+    assert_eq!(model.min_resources, 10);
+    assert!(model.active);
+    assert_eq!(model.weight_hash, content_hash(&weights_v2));
+    assert_eq!(holders(&task_manager), assignment);
+    assert_eq!(task_manager.get_reassembled_model("model1"), Ok(weights_v2));
+
+    let versions = task_manager.get_model_versions("model1").unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0].model.weight_hash, content_hash(&weights_v1));
+    assert_eq!(versions[1].published_at, 5);
+
+    assert_eq!(task_manager.rollback_model("model1", 20), Ok("1.0.0".to_string()));
+    assert_eq!(task_manager.get_model("model1").unwrap().version, "1.0.0");
+    assert_eq!(holders(&task_manager), assignment);
+    assert_eq!(task_manager.get_reassembled_model("model1"), Ok(weights_v1));
+    assert_eq!(
+        task_manager.rollback_model("model1", 30),
+        Err(TaskManagerError::NoPreviousModelVersion {
+            model_id: "model1".to_string()
+        })
+    );
+}
+
+#[test]
+fn test_reconcile_model_capacity() {
+    let mut task_manager = TaskManagerImpl::default();
+    let user = User {
+        id: "user1".to_string(),
+        resources: 100,
+        rewards: 0,
+        rate_limit_tokens: 10,
+        owner: None,
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
+    };
+    task_manager.register_user(user).unwrap();
+    let models = [
+        ("degrading", CapacityPolicy::Degrade),
+        ("deactivating", CapacityPolicy::Deactivate),
+        ("manual", CapacityPolicy::Manual),
+    ];
+    for (id, capacity_policy) in models {
+        let model = Model {
+            id: id.to_string(),
+            min_resources: 150,
+            version: "1.0.0".to_string(),
+            capacity_policy,
+            ..Default::default()
+        };
+        task_manager.register_model(model, 0).unwrap();
+    }
+    let kinds = |events: Vec<ModelEvent>| -> Vec<(String, ModelEventKind)> {
+        events.into_iter().map(|event| (event.model_id, event.kind)).collect()
+    };
+
+    assert!(task_manager.reconcile_model_capacity(1).is_empty());
+    task_manager.update_user_resources("user1", 600).unwrap();
+    for (id, _) in models {
+        task_manager.pledge_resources("user1", id, 200).unwrap();
+    }
+    assert_eq!(
+        kinds(task_manager.reconcile_model_capacity(2)),
+        vec![
+            ("deactivating".to_string(), ModelEventKind::Activated),
+            ("degrading".to_string(), ModelEventKind::Activated),
+        ]
+    );
+    assert!(!task_manager.get_model("manual").unwrap().active);
+    assert!(task_manager.reconcile_model_capacity(3).is_empty());
+
+    for (id, _) in models {
+        task_manager.pledge_resources("user1", id, 50).unwrap();
+    }
+    assert_eq!(
+        kinds(task_manager.reconcile_model_capacity(4)),
+        vec![
+            ("deactivating".to_string(), ModelEventKind::Deactivated),
+            ("degrading".to_string(), ModelEventKind::Degraded),
+        ]
+    );
+    let degrading = task_manager.get_model("degrading").unwrap();
+    assert!(degrading.active && degrading.degraded);
+
+    for (id, _) in models {
+        task_manager.pledge_resources("user1", id, 150).unwrap();
+    }
+    let events = task_manager.reconcile_model_capacity(5);
+    assert_eq!(
+        kinds(events.clone()),
+        vec![
+            ("deactivating".to_string(), ModelEventKind::Activated),
+            ("degrading".to_string(), ModelEventKind::Recovered),
+        ]
+    );
+    assert_eq!(events[1].capacity, 150);
+    assert_eq!(events[1].timestamp, 5);
+
+    let history = task_manager.get_model_events(None, 100);
+    assert_eq!(history.len(), 6);
+    assert_eq!(task_manager.get_model_events(Some(3), 100), history[4..].to_vec());
+}
+
+#[test]
+fn test_resource_pledges() {
+    let mut task_manager = TaskManagerImpl::default();
+    for id in ["user1", "user2"] {
+        let user = User {
+            id: id.to_string(),
+            resources: 100,
+            rewards: 0,
+            rate_limit_tokens: 10,
+            owner: None,
+            tier: RateLimitTier::Free,
+            rate_limit_refilled_at: 0,
+        };
+        task_manager.register_user(user).unwrap();
+    }
+    for id in ["model1", "model2"] {
+        let model = Model {
+            id: id.to_string(),
+            min_resources: 150,
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+        task_manager.register_model(model, 0).unwrap();
+    }
+
+    task_manager.pledge_resources("user1", "model1", 60).unwrap();
+    task_manager.pledge_resources("user1", "model2", 40).unwrap();
+    // The same resources cannot back a second model.
+    assert_eq!(
+        task_manager.pledge_resources("user1", "model2", 41),
+        Err(TaskManagerError::PledgeExceedsResources {
+            user_id: "user1".to_string(),
+            pledged: 101,
+            resources: 100,
+        })
+    );
+    // Changing a pledge replaces it rather than adding to it.
+    task_manager.pledge_resources("user1", "model1", 50).unwrap();
+    task_manager.pledge_resources("user2", "model1", 100).unwrap();
+    assert_eq!(task_manager.get_model_capacity("model1"), Ok(150));
+    assert_eq!(task_manager.get_model_capacity("model2"), Ok(40));
+    assert!(task_manager.activate_model("model1").is_ok());
+    assert!(task_manager.activate_model("model2").is_err());
+    let query = ModelQuery {
+        limit: 10,
+        ..Default::default()
+    };
+    let needing: Vec<String> = task_manager
+        .get_models_needing_resources(&query)
+        .unwrap()
+        .models
+        .into_iter()
+        .map(|model| model.id)
+        .collect();
+    assert_eq!(needing, vec!["model2"]);
+
+    assert_eq!(
+        task_manager.update_user_resources("user1", 80),
+        Err(TaskManagerError::PledgeExceedsResources {
+            user_id: "user1".to_string(),
+            pledged: 90,
+            resources: 80,
+        })
+    );
+    task_manager.pledge_resources("user1", "model2", 0).unwrap();
+    task_manager.update_user_resources("user1", 80).unwrap();
+    assert_eq!(
+        task_manager.get_user_pledges("user1"),
+        Ok(vec![ResourcePledge {
+            model_id: "model1".to_string(),
+            amount: 50,
+        }])
+    );
+}
+
+#[test]
+fn test_model_pagination() {
+    let mut task_manager = TaskManagerImpl::default();
+    let user = User {
+        id: "user1".to_string(),
+        resources: 100,
+        rewards: 0,
+        rate_limit_tokens: 10,
+        owner: None,
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
+    };
+    task_manager.users.insert(user.id.clone(), user);
+    for (id, min_resources, now) in [("c", 30, 1), ("a", 10, 3), ("d", 10, 2), ("b", 20, 4)] {
+        let mut model = Model {
+            id: id.to_string(),
+            min_resources,
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+        if id == "b" {
+            model.architecture = ModelArchitecture::Gpt2;
+            model.backend = InferenceBackend::Onnx;
+        }
+        task_manager.register_model(model, now).unwrap();
+        task_manager.pledge_resources("user1", id, min_resources).unwrap();
+        task_manager.activate_model(id).unwrap();
+    }
+
+    // Pages of two, walked through the cursor, cover every model exactly once.
+    let mut query = ModelQuery {
+        limit: 2,
+        sort_by: ModelSortKey::MinResources,
+        ..Default::default()
+    };
+    let mut ids = Vec::new();
+    loop {
+        let page = task_manager.get_active_models(&query).unwrap();
+        assert_eq!(page.total, 4);
+        ids.extend(page.models.into_iter().map(|model| model.id));
+        match page.next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    // Equal keys are ordered by id.
+    assert_eq!(ids, vec!["a", "d", "b", "c"]);
+
+    // A model added behind the cursor does not shift the following page.
+    query.sort_by = ModelSortKey::CreatedAt;
+    query.descending = true;
+    query.cursor = None;
+    let first = task_manager.get_active_models(&query).unwrap();
+    let first_ids: Vec<String> = first.models.into_iter().map(|model| model.id).collect();
+    assert_eq!(first_ids, vec!["b", "a"]);
+    let newer = Model {
+        id: "e".to_string(),
+        version: "1.0.0".to_string(),
+        ..Default::default()
+    };
+    task_manager.register_model(newer, 5).unwrap();
+    task_manager.activate_model("e").unwrap();
+    query.cursor = first.next_cursor;
+    let second = task_manager.get_active_models(&query).unwrap();
+    let second_ids: Vec<String> = second.models.into_iter().map(|model| model.id).collect();
+    assert_eq!(second_ids, vec!["d", "c"]);
+    assert_eq!(second.total, 5);
+    assert_eq!(second.next_cursor, None);
+
+    let filtered = ModelQuery {
+        limit: 10,
+        architecture: Some(ModelArchitecture::Gpt2),
+        backend: Some(InferenceBackend::Onnx),
+        ..Default::default()
+    };
+    let page = task_manager.get_active_models(&filtered).unwrap();
+    assert_eq!(page.total, 1);
+    assert_eq!(page.models[0].id, "b");
+
+    let malformed = ModelQuery {
+        limit: 10,
+        cursor: Some("not-a-cursor".to_string()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        task_manager.get_active_models(&malformed),
+        Err(TaskManagerError::InvalidArgument { .. })
+    ));
+    let unbounded = ModelQuery {
+        limit: MAX_MODEL_PAGE_SIZE + 1,
+        ..Default::default()
+    };
+    assert!(matches!(
+        task_manager.get_active_models(&unbounded),
+        Err(TaskManagerError::InvalidArgument { .. })
+    ));
+}
+
+#[test]
+fn test_training_task_lifecycle() {
+    let mut task_manager = TaskManagerImpl::default();
+    let tasks = [("task2", "model1", 20), ("task1", "model1", 10), ("task3", "model2", 30)];
+    for (id, model_id, now) in tasks {
+        let task = TrainingTask {
+            id: id.to_string(),
+            model_id: model_id.to_string(),
+            training_data: vec![1, 2, 3],
+            model_weights: None,
+            // Ignored: the canister owns the lifecycle fields.
+            status: TrainingTaskStatus::Validated,
+            ..Default::default()
+        };
+        task_manager.create_training_task(task, now).unwrap();
+    }
+    assert_eq!(
+        task_manager.get_training_task("task1").unwrap().status,
+        TrainingTaskStatus::Pending
+    );
+
+    // Claims hand out the oldest pending task.
+    let claimed = task_manager.claim_training_task("user1", 40).unwrap();
+    assert_eq!(claimed.id, "task1");
+    assert_eq!(claimed.status, TrainingTaskStatus::Assigned);
+    assert_eq!(claimed.assignee, Some("user1".to_string()));
+    assert_eq!(claimed.assigned_at, Some(40));
+    assert_eq!(
+        task_manager.start_training_task("user2", "task1", 41),
+        Err(TaskManagerError::TrainingTaskNotAssigned {
+            task_id: "task1".to_string(),
+            user_id: "user2".to_string(),
+        })
+    );
+    task_manager.start_training_task("user1", "task1", 41).unwrap();
+    assert_eq!(
+        task_manager.validate_training_task("task1", 42),
+        Err(TaskManagerError::InvalidTrainingTaskTransition {
+            task_id: "task1".to_string(),
+            from: TrainingTaskStatus::Running,
+            to: TrainingTaskStatus::Validated,
+        })
+    );
+    task_manager
+        .submit_training_results("user1", "task1", vec![9; 4], 0, 43)
+        .unwrap();
+    task_manager.validate_training_task("task1", 44).unwrap();
+    let task = task_manager.get_training_task("task1").unwrap();
+    assert_eq!(task.status, TrainingTaskStatus::Validated);
+    assert_eq!(task.model_weights, Some(vec![9; 4]));
+    assert_eq!((task.submitted_at, task.finished_at), (Some(43), Some(44)));
+    // The results hash is stored on submission and served with every slice.
+    assert_eq!(task.results_hash, content_hash(&[9; 4]));
+    let source = DownloadSource::TrainingResults {
+        task_id: "task1".to_string(),
+    };
+    let slice = task_manager.read_download(&source, 1, 2).unwrap();
+    assert_eq!(slice.data, vec![9; 2]);
+    assert_eq!(slice.content_hash, task.results_hash);
+    // Results saved before the hash was stored get it on restore.
+    task_manager.training_tasks.get_mut("task1").unwrap().results_hash.clear();
+    let restored = decode_state(&encode_state(&task_manager).unwrap()).unwrap();
+    assert_eq!(restored.training_tasks["task1"].results_hash, content_hash(&[9; 4]));
+    assert!(task_manager.cancel_training_task("task1", 45).is_err());
+
+    let claimed = task_manager.claim_training_task("user2", 50).unwrap();
+    assert_eq!(claimed.id, "task2");
+    task_manager
+        .submit_training_results("user2", "task2", vec![1], 0, 51)
+        .unwrap();
+    task_manager
+        .reject_training_task("task2", "loss diverged".to_string(), 52)
+        .unwrap();
+    let task = task_manager.get_training_task("task2").unwrap();
+    assert_eq!(task.status, TrainingTaskStatus::Failed);
+    assert_eq!(task.error, Some("loss diverged".to_string()));
+
+    task_manager.cancel_training_task("task3", 60).unwrap();
+    assert!(task_manager.claim_training_task("user1", 61).is_none());
+
+    let ids = |page: TrainingTaskPage| -> Vec<String> {
+        page.tasks.into_iter().map(|task| task.id).collect()
+    };
+    let query = TrainingTaskQuery {
+        cursor: None,
+        limit: 10,
+    };
+    assert_eq!(
+        ids(task_manager.get_training_tasks_by_model("model1", &query).unwrap()),
+        vec!["task1", "task2"]
+    );
+    let cancelled = TrainingTaskStatus::Cancelled;
+    assert_eq!(
+        ids(task_manager.get_training_tasks_by_status(cancelled, &query).unwrap()),
+        vec!["task3"]
+    );
+    assert!(task_manager
+        .get_training_tasks_by_status(TrainingTaskStatus::Pending, &query)
+        .unwrap()
+        .tasks
+        .is_empty());
+
+    // Listings page by cursor and leave out the data and weights.
+    let first = TrainingTaskQuery {
+        cursor: None,
+        limit: 1,
+    };
+    let page = task_manager.get_training_tasks_by_model("model1", &first).unwrap();
+    assert_eq!(page.total, 2);
+    assert_eq!(page.tasks[0].id, "task1");
+    assert_eq!(page.tasks[0].results_size, Some(4));
+    let next = TrainingTaskQuery {
+        cursor: page.next_cursor,
+        limit: 1,
+    };
+    let page = task_manager.get_training_tasks_by_model("model1", &next).unwrap();
+    assert_eq!(ids(page.clone()), vec!["task2"]);
+    assert_eq!(page.next_cursor, None);
+    let too_large = TrainingTaskQuery {
+        cursor: None,
+        limit: MAX_TRAINING_TASK_PAGE_SIZE + 1,
+    };
+    assert!(task_manager.get_training_tasks_by_model("model1", &too_large).is_err());
+}
+
+#[test]
+fn test_training_config_validation() {
+    let mut task_manager = TaskManagerImpl::default();
+    let config = TrainingConfig {
+        optimizer: Optimizer::Sgd,
+        learning_rate: 0.1,
+        lr_schedule: LearningRateSchedule::Cosine,
+        warmup_steps: 100,
+        batch_size: 32,
+        epochs: 1,
+        max_sequence_length: 512,
+        weight_decay: 0.01,
+        seed: 7,
+    };
+    assert_eq!(config.validate(), Ok(()));
+    let invalid = [
+        ("learning_rate", TrainingConfig { learning_rate: 0.0, ..config.clone() }),
+        ("learning_rate", TrainingConfig { learning_rate: f64::NAN, ..config.clone() }),
+        ("batch_size", TrainingConfig { batch_size: 0, ..config.clone() }),
+        ("batch_size", TrainingConfig { batch_size: MAX_BATCH_SIZE + 1, ..config.clone() }),
+        ("epochs", TrainingConfig { epochs: 0, ..config.clone() }),
+        ("max_sequence_length", TrainingConfig { max_sequence_length: 0, ..config.clone() }),
+        ("weight_decay", TrainingConfig { weight_decay: -0.1, ..config.clone() }),
+    ];
+    for (argument, config) in invalid {
+        let task = TrainingTask {
+            id: "task1".to_string(),
+            model_id: "model1".to_string(),
+            config,
+            ..Default::default()
+        };
+        match task_manager.create_training_task(task, 0) {
+            Err(TaskManagerError::InvalidArgument { argument: rejected, .. }) => {
+                assert_eq!(rejected, argument)
+            }
+            _ => panic!("expected {} to be rejected", argument),
+        }
+    }
+
+    // Workers receive the configuration with the task they claim.
+    let task = TrainingTask {
+        id: "task1".to_string(),
+        model_id: "model1".to_string(),
+        config: config.clone(),
+        ..Default::default()
+    };
+    task_manager.create_training_task(task, 0).unwrap();
+    assert_eq!(task_manager.claim_training_task("user1", 1).unwrap().config, config);
+}
+
+#[test]
+fn test_federated_training_round() {
+    let mut task_manager = TaskManagerImpl::default();
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 0,
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
+    };
+    task_manager.register_model(model, 0).unwrap();
+    task_manager.activate_model("model1").unwrap();
+    task_manager
+        .upload_model_weights("model1", encode_weights(&[1.0, 2.0]))
+        .unwrap();
+    let request = TrainingRoundRequest {
+        model_id: "model1".to_string(),
+        target_version: "1.1.0".to_string(),
+        training_data: vec![vec![1], vec![2], vec![3]],
+        config: TrainingConfig::default(),
+        min_submissions: 2,
+        aggregator: Aggregator::FedAvg,
+        clip_norm: None,
+    };
+    assert!(matches!(
+        task_manager.start_training_round(
+            TrainingRoundRequest {
+                target_version: "1.0.0".to_string(),
+                ..request.clone()
+            },
+            1
+        ),
+        Err(TaskManagerError::ModelVersionAlreadyExists { .. })
+    ));
+    let round = task_manager.start_training_round(request, 1).unwrap();
+    assert_eq!(round.base_version, "1.0.0");
+    assert_eq!(round.task_ids.len(), 3);
+
+    // Each user takes at most one task of a round.
+    let first = task_manager.claim_training_task("user1", 2).unwrap();
+    assert!(task_manager.claim_training_task("user1", 2).is_none());
+    let second = task_manager.claim_training_task("user2", 2).unwrap();
+    assert_eq!(first.round_id, Some(round.id.clone()));
+
+    assert!(matches!(
+        task_manager.submit_training_results("user1", &first.id, vec![0; 4], 1, 3),
+        Err(TaskManagerError::InvalidArgument { .. })
+    ));
+    task_manager
+        .submit_training_results("user1", &first.id, encode_weights(&[1.0, 0.0]), 1, 3)
+        .unwrap();
+    assert_eq!(
+        task_manager.finalize_training_round(&round.id, 4),
+        Err(TaskManagerError::NotEnoughContributors {
+            required: 2,
+            available: 1,
+        })
+    );
+    task_manager
+        .submit_training_results("user2", &second.id, encode_weights(&[0.0, 4.0]), 3, 5)
+        .unwrap();
+
+    // Deltas are weighted 1:3 by their sample counts.
+    let published = task_manager.finalize_training_round(&round.id, 6).unwrap();
+    assert_eq!(published.status, TrainingRoundStatus::Published);
+    assert_eq!(published.aggregated_tasks, vec![first.id.clone(), second.id.clone()]);
+    let model = task_manager.get_model("model1").unwrap();
+    assert_eq!(model.version, "1.1.0");
+    assert!(model.active);
+    assert_eq!(
+        decode_weights(task_manager.model_weights.get("model1").unwrap()).unwrap(),
+        vec![1.25, 5.0]
+    );
+    let statuses: Vec<TrainingTaskStatus> = round
+        .task_ids
+        .iter()
+        .map(|task_id| task_manager.get_training_task(task_id).unwrap().status)
+        .collect();
+    assert_eq!(
+        statuses,
+        vec![
+            TrainingTaskStatus::Validated,
+            TrainingTaskStatus::Validated,
+            TrainingTaskStatus::Cancelled,
+        ]
+    );
+    assert_eq!(
+        task_manager.finalize_training_round(&round.id, 7),
+        Err(TaskManagerError::TrainingRoundClosed {
+            round_id: round.id.clone(),
+        })
+    );
+    assert_eq!(task_manager.rollback_model("model1", 8), Ok("1.0.0".to_string()));
+}
+
+#[test]
+fn test_robust_aggregators() {
+    let base = [10.0, 10.0];
+    // Four honest deltas near (1, -1) and one poisoned delta.
+    let deltas: Vec<(Vec<f32>, u64)> = vec![
+        (vec![0.8, -1.2], 1),
+        (vec![1.0, -1.0], 1),
+        (vec![1.0, -1.0], 1),
+        (vec![1.0, -1.0], 1),
+        (vec![1000.0, 1000.0], 1),
+    ];
+    assert_eq!(
+        aggregate(Aggregator::FedAvg, None, &base, &deltas).unwrap(),
+        vec![210.76, 209.16]
+    );
+    assert_eq!(
+        aggregate(Aggregator::Median, None, &base, &deltas).unwrap(),
+        vec![11.0, 9.0]
+    );
+    assert_eq!(
+        aggregate(Aggregator::TrimmedMean { trim: 1 }, None, &base, &deltas).unwrap(),
+        vec![11.0, 9.0]
+    );
+    assert_eq!(
+        aggregate(Aggregator::Krum { byzantine: 1 }, None, &base, &deltas).unwrap(),
+        vec![11.0, 9.0]
+    );
+    // Sample counts whose sum overflows u64 still weight the average correctly.
+    let heavy = vec![(vec![1.0, 1.0], u64::MAX), (vec![3.0, 3.0], u64::MAX)];
+    assert_eq!(
+        aggregate(Aggregator::FedAvg, None, &base, &heavy).unwrap(),
+        vec![12.0, 12.0]
+    );
+    // Clipping bounds the poisoned delta's pull on the plain average.
+    let clipped = aggregate(Aggregator::FedAvg, Some(2.0), &base, &deltas).unwrap();
+    assert!(clipped.iter().all(|weight| (*weight - 10.0).abs() < 2.0));
+    assert!(matches!(
+        aggregate(Aggregator::TrimmedMean { trim: 3 }, None, &base, &deltas),
+        Err(TaskManagerError::InvalidArgument { .. })
+    ));
+
+    assert_eq!(Aggregator::Krum { byzantine: 1 }.validate(5), Ok(()));
+    assert!(Aggregator::Krum { byzantine: u32::MAX }.validate(u32::MAX).is_err());
+    assert!(Aggregator::TrimmedMean { trim: u32::MAX }.validate(u32::MAX).is_err());
+    assert_eq!(
+        aggregate(Aggregator::Krum { byzantine: u32::MAX }, None, &base, &deltas).unwrap(),
+        vec![11.0, 9.0]
+    );
+    assert!(aggregate(Aggregator::TrimmedMean { trim: u32::MAX }, None, &base, &deltas).is_err());
+    assert!(Aggregator::Krum { byzantine: 1 }.validate(4).is_err());
+    assert!(Aggregator::TrimmedMean { trim: 1 }.validate(2).is_err());
+    assert_eq!(Aggregator::Median.validate(1), Ok(()));
+}
+
+#[test]
+fn test_multipart_upload() {
+    let mut task_manager = TaskManagerImpl::default();
+    let admin = Principal::from_text("2vxsx-fae").unwrap();
+    let other = Principal::management_canister();
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 0,
+        active: false,
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
+    };
+    task_manager.register_model(model, 0).unwrap();
+    let part_size = MIN_UPLOAD_PART_SIZE as usize;
+    let weights: Vec<u8> = (0..2 * part_size + 10).map(|i| i as u8).collect();
+    let total_size = weights.len() as u64;
+    let target = UploadTarget::ModelWeights {
+        model_id: "model1".to_string(),
+    };
+    for bad_part_size in [0, MIN_UPLOAD_PART_SIZE - 1, MAX_UPLOAD_PART_SIZE + 1] {
+        assert!(matches!(
+            task_manager.begin_upload(
+                admin,
+                target.clone(),
+                total_size,
+                bad_part_size,
+                content_hash(&weights),
+                0
+            ),
+            Err(TaskManagerError::InvalidArgument { .. })
+        ));
+    }
+    let status = task_manager
+        .begin_upload(admin, target, total_size, part_size as u64, content_hash(&weights), 0)
+        .unwrap();
+    assert_eq!(status.total_parts, 3);
+
+    // Parts arrive out of order; a corrupted part is refused and can be sent again.
+    let part = |index: usize| {
+        weights[index * part_size..((index + 1) * part_size).min(weights.len())].to_vec()
+    };
+    task_manager
+        .upload_part(&admin, &status.id, 2, part(2), content_hash(&part(2)), 1)
+        .unwrap();
+    let corrupted = vec![9; part_size];
+    assert_eq!(
+        task_manager.upload_part(&admin, &status.id, 0, corrupted, content_hash(&part(0)), 1),
+        Err(TaskManagerError::UploadIntegrityFailed {
+            upload_id: status.id.clone(),
+            part: Some(0),
+        })
+    );
+    assert!(matches!(
+        task_manager.upload_part(&admin, &status.id, 1, vec![1; 3], content_hash(&[1; 3]), 1),
+        Err(TaskManagerError::InvalidArgument { .. })
+    ));
+    assert_eq!(
+        task_manager.upload_part(&other, &status.id, 0, part(0), content_hash(&part(0)), 1),
+        Err(TaskManagerError::NotUploadOwner {
+            upload_id: status.id.clone(),
+        })
+    );
+    task_manager
+        .upload_part(&admin, &status.id, 0, part(0), content_hash(&part(0)), 2)
+        .unwrap();
+    assert_eq!(
+        task_manager.get_upload(&admin, &status.id).unwrap().received_parts,
+        vec![0, 2]
+    );
+    assert_eq!(
+        task_manager.commit_upload(&admin, &status.id, 3),
+        Err(TaskManagerError::UploadIncomplete {
+            upload_id: status.id.clone(),
+            received: 2,
+            expected: 3,
+        })
+    );
+    task_manager
+        .upload_part(&admin, &status.id, 1, part(1), content_hash(&part(1)), 3)
+        .unwrap();
+    task_manager.commit_upload(&admin, &status.id, 4).unwrap();
+    assert_eq!(task_manager.model_weights.get("model1"), Some(&weights));
+    assert_eq!(
+        task_manager.get_model("model1").unwrap().weight_hash,
+        content_hash(&weights)
+    );
+    assert!(matches!(
+        task_manager.get_upload(&admin, &status.id),
+        Err(TaskManagerError::UploadNotFound { .. })
+    ));
+
+    // Training data lands in a new task; uploads nobody touches for a day expire.
+    let target = UploadTarget::TrainingData {
+        task: Box::new(TrainingTask {
+            id: "task1".to_string(),
+            model_id: "model1".to_string(),
+            ..Default::default()
+        }),
+    };
+    let status = task_manager
+        .begin_upload(admin, target, 2, 2, content_hash(&[7, 7]), 10)
+        .unwrap();
+    task_manager
+        .upload_part(&admin, &status.id, 0, vec![7, 7], content_hash(&[7, 7]), 11)
+        .unwrap();
+    task_manager.commit_upload(&admin, &status.id, 12).unwrap();
+    assert_eq!(task_manager.get_training_task("task1").unwrap().training_data, vec![7, 7]);
+
+    let target = UploadTarget::ModelWeights {
+        model_id: "model1".to_string(),
+    };
+    let status = task_manager
+        .begin_upload(admin, target, 1, 1, content_hash(&[1]), 20)
+        .unwrap();
+    assert!(matches!(
+        task_manager.upload_part(
+            &admin,
+            &status.id,
+            0,
+            vec![1],
+            content_hash(&[1]),
+            20 + UPLOAD_EXPIRY_NS
+        ),
+        Err(TaskManagerError::UploadNotFound { .. })
+    ));
+    assert_eq!(task_manager.drop_expired_uploads(20 + UPLOAD_EXPIRY_NS), 1);
+    assert_eq!(task_manager.drop_expired_uploads(20 + UPLOAD_EXPIRY_NS), 0);
+
+    // Each principal holds only a few open uploads, and all of them together only so many bytes.
+    let target = UploadTarget::ModelWeights {
+        model_id: "model1".to_string(),
+    };
+    let begin = |task_manager: &mut TaskManagerImpl, owner: Principal, total_size: u64| {
+        let part_size = MAX_UPLOAD_PART_SIZE;
+        task_manager.begin_upload(owner, target.clone(), total_size, part_size, vec![0; 32], 30)
+    };
+    let open: Vec<UploadStatus> = (0..MAX_OPEN_UPLOADS_PER_OWNER)
+        .map(|_| begin(&mut task_manager, admin, MAX_UPLOAD_SIZE).unwrap())
+        .collect();
+    assert_eq!(
+        begin(&mut task_manager, admin, 1),
+        Err(TaskManagerError::TooManyOpenUploads {
+            limit: MAX_OPEN_UPLOADS_PER_OWNER,
+        })
+    );
+    assert_eq!(
+        begin(&mut task_manager, other, 1),
+        Err(TaskManagerError::UploadStorageFull)
+    );
+    task_manager.abort_upload(&admin, &open[0].id).unwrap();
+    begin(&mut task_manager, other, 1).unwrap();
+}
+
+#[test]
+fn test_chunked_download() {
+    let mut task_manager = TaskManagerImpl::default();
+    let model = Model {
+        id: "model1".to_string(),
+        min_resources: 0,
+        active: false,
+        version: "1.0.0".to_string(),
+        backend: InferenceBackend::Onnx,
+        ..Default::default()
+    };
+    task_manager.register_model(model, 0).unwrap();
+    let weights: Vec<u8> = (0..10u8).collect();
+    task_manager.upload_model_weights("model1", weights.clone()).unwrap();
+
+    let source = DownloadSource::ModelWeights {
+        model_id: "model1".to_string(),
+    };
+    let slice = task_manager.read_download(&source, 8, 4).unwrap();
+    assert_eq!(slice.data, vec![8, 9]);
+    assert_eq!(slice.total_size, 10);
+    assert_eq!(slice.slice_hash, content_hash(&[8, 9]));
+    assert_eq!(slice.content_hash, content_hash(&weights));
+    for (offset, length) in [(10, 1), (0, 0), (0, MAX_DOWNLOAD_LENGTH + 1)] {
+        assert!(matches!(
+            task_manager.read_download(&source, offset, length),
+            Err(TaskManagerError::InvalidArgument { .. })
+        ));
+    }
+    let missing = DownloadSource::ModelVersionWeights {
+        model_id: "model1".to_string(),
+        version: "2.0.0".to_string(),
+    };
+    assert!(task_manager.read_download(&missing, 0, 1).is_err());
+
+    // Over HTTP the first slice is served with the headers, the rest through the callback.
+    let route = download_route(request_path("/models/model1/weights?v=1")).unwrap();
+    assert_eq!(route, source);
+    assert_eq!(download_route("/models/model1"), None);
+    let callback = Func {
+        principal: Principal::anonymous(),
+        method: "http_request_streaming_callback".to_string(),
+    };
+    let response = serve_download(&task_manager, route, callback.clone());
+    assert_eq!(response.status_code, 200);
+    assert_eq!(response.body, weights);
+    assert!(response.streaming_strategy.is_none());
+    let response = serve_download(
+        &task_manager,
+        DownloadSource::ModelChunk {
+            chunk_id: "missing".to_string(),
+        },
+        callback,
+    );
+    assert_eq!(response.status_code, 404);
+    let token = StreamingCallbackToken { source, offset: 4 };
+    let streamed = stream_download(&task_manager, token);
+    assert_eq!(streamed.body, weights[4..]);
+    assert!(streamed.token.is_none());
+}
+
+fn api_request(method: &str, url: &str, key: Option<&str>, body: &str) -> HttpRequest {
+    HttpRequest {
+        method: method.to_string(),
+        url: url.to_string(),
+        headers: key
+            .map(|key| vec![("Authorization".to_string(), format!("Bearer {}", key))])
+            .unwrap_or_default(),
+        body: body.as_bytes().to_vec(),
+    }
+}
+
+#[test]
+fn test_http_gateway() {
+    let mut task_manager = TaskManagerImpl::default();
+    let user = User {
+        id: "user1".to_string(),
+        resources: 100,
+        rewards: 7,
+        rate_limit_tokens: 10,
+        owner: None,
+        tier: RateLimitTier::Free,
+        rate_limit_refilled_at: 0,
+    };
+    task_manager.register_user(user).unwrap();
+    task_manager.users.get_mut("user1").unwrap().rewards = 7;
+    let key = "emris-0123456789abcdef0123456789abcdef";
+    assert!(task_manager.add_api_key("user1", "short").is_err());
+    assert!(task_manager.add_api_key("user2", key).is_err());
+    task_manager.add_api_key("user1", key).unwrap();
+    assert_eq!(task_manager.find_api_key_user(key), Ok("user1".to_string()));
+    for id in ["model1", "model2"] {
+        let model = Model {
+            id: id.to_string(),
+            min_resources: 0,
+            version: "1.0.0".to_string(),
+            ..Default::default()
+        };
+        task_manager.register_model(model, 0).unwrap();
+        task_manager.activate_model(id).unwrap();
+    }
+    task_manager.set_text_generator("model1", Box::new(EchoGenerator));
+
+    let response =
+        handle_api_query(&task_manager, &api_request("GET", "/api/models?limit=1", None, ""));
+    assert_eq!(response.status_code, 200);
+    let page: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(page["models"][0]["id"], "model1");
+    assert_eq!(page["total"], 2);
+    let response = handle_api_query(
+        &task_manager,
+        &api_request("GET", "/api/models?sort_by=size", None, ""),
+    );
+    assert_eq!(response.status_code, 400);
+    let response =
+        handle_api_query(&task_manager, &api_request("GET", "/api/models/missing", None, ""));
+    assert_eq!(response.status_code, 404);
+    let error: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(error["error"]["ModelNotFound"]["model_id"], "missing");
+
+    // Per-user routes need a valid key.
+    let response = handle_api_query(&task_manager, &api_request("GET", "/api/rewards", None, ""));
+    assert_eq!(response.status_code, 401);
+    let wrong = Some("emris-ffffffffffffffffffffffffffffffff");
+    let response = handle_api_query(&task_manager, &api_request("GET", "/api/rewards", wrong, ""));
+    assert_eq!(response.status_code, 401);
+    let response =
+        handle_api_query(&task_manager, &api_request("GET", "/api/rewards", Some(key), ""));
+    let rewards: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(rewards["rewards"], 7);
+
+    // Completions are upgraded to an update call and cost a rate-limit token.
+    let request = api_request(
+        "POST",
+        "/api/completions",
+        Some(key),
+        r#"{"prompt": "hello", "max_new_tokens": 2}"#,
+    );
+    assert_eq!(handle_api_query(&task_manager, &request).upgrade, Some(true));
+    let response = handle_api_update(&mut task_manager, &request, 5);
+    assert_eq!(response.status_code, 201);
+    let completion: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+    assert_eq!(completion["generated_text"], "HELLO");
+    assert!(task_manager.users["user1"].rate_limit_tokens < 10);
+    let completion_id = completion["id"].as_str().unwrap();
+    let url = format!("/api/completions/{}", completion_id);
+    let response = handle_api_query(&task_manager, &api_request("GET", &url, Some(key), ""));
+    assert_eq!(response.status_code, 200);
+    let request = api_request("POST", "/api/completions", Some(key), "not json");
+    assert_eq!(handle_api_update(&mut task_manager, &request, 5).status_code, 400);
+    let response = handle_api_update(
+        &mut task_manager,
+        &api_request("POST", "/api/unknown", Some(key), "{}"),
+        5,
+    );
+    assert_eq!(response.status_code, 404);
+
+    let limited = TaskManagerError::RateLimited {
+        retry_after_ns: 1_500_000_000,
+    };
+    assert_eq!(error_status(&limited), 429);
+    assert_eq!(error_status(&TaskManagerError::NoActiveModels), 503);
+    assert_eq!(error_status(&TaskManagerError::NotRegisteredUser), 403);
+
+    assert_eq!(task_manager.revoke_api_keys("user1"), Ok(1));
+    let response =
+        handle_api_query(&task_manager, &api_request("GET", "/api/rewards", Some(key), ""));
+    assert_eq!(response.status_code, 401);
 }
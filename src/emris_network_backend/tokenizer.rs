@@ -1,72 +1,35 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use tokenizers::models::bpe::BpeBuilder;
-use tokenizers::pre_tokenizers::whitespace::Whitespace;
-use tokenizers::tokenizer::{AddedToken, Tokenizer};
+use crate::errors::TaskManagerError;
+use tokenizers::tokenizer::Tokenizer;
 
-pub struct SimpleTokenizer {
-    tokenizer: Tokenizer,
-}
-
-impl SimpleTokenizer {
-    pub fn new() -> Self {
-        let mut tokenizer = Tokenizer::new();
-        let file = File::open("models/tokenization/simple_tokens.json").unwrap();
-        let reader = BufReader::new(file);
-        let encoder: HashMap<String, u32> = serde_json::from_reader(reader).unwrap();
-
-        let mut decoder: HashMap<u32, String> = HashMap::new();
-        for (key, value) in encoder {
-            decoder.insert(value, key);
-        }
-
-        tokenizer.add_tokens(
-            decoder
-                .iter()
-                .map(|(_, token)| AddedToken::from(String::from(token)))
-                .collect::<Vec<AddedToken>>(),
-        );
-
-        tokenizer.with_pre_tokenizer(Whitespace::default());
-
-        SimpleTokenizer { tokenizer }
-    }
-
-    pub fn encode(&self, input: &str) -> Vec<u32> {
-        self.tokenizer.encode(input, true).unwrap().get_ids()
-    }
-
-    pub fn decode(&self, input: &[u32]) -> String {
-        self.tokenizer.decode(input.to_vec(), true).unwrap()
-    }
+fn tokenizer_failed(error: impl std::fmt::Display) -> TaskManagerError {
+    TaskManagerError::InferenceFailed(format!("tokenizer: {}", error))
 }
 
+// GPT-2 byte-level BPE tokenizer, built from the `tokenizer.json` uploaded with the model since a
+// canister has no filesystem to read vocabulary files from.
 pub struct GPT2Tokenizer {
     tokenizer: Tokenizer,
 }
 
 impl GPT2Tokenizer {
-    pub fn new() -> Self {
-        let mut tokenizer = Tokenizer::new();
-
-        let bpe = BpeBuilder::from_files("models/tokenization/vocab.bpe")
-            .unwrap()
-            .dropout(None)
-            .build()
-            .unwrap();
-
-        tokenizer.with_model(bpe);
-        tokenizer.with_pre_tokenizer(Whitespace::default());
+    pub fn from_bytes(tokenizer_json: &[u8]) -> Result<Self, TaskManagerError> {
+        let tokenizer = Tokenizer::from_bytes(tokenizer_json).map_err(tokenizer_failed)?;
+        Ok(GPT2Tokenizer { tokenizer })
+    }
 
-        GPT2Tokenizer { tokenizer }
+    // Tokens the vocabulary holds, including added special tokens.
+    pub fn vocab_size(&self) -> usize {
+        self.tokenizer.get_vocab_size(true)
     }
 
-    pub fn encode(&self, input: &str) -> Vec<u32> {
-        self.tokenizer.encode(input, true).unwrap().get_ids()
+    pub fn encode(&self, input: &str) -> Result<Vec<u32>, TaskManagerError> {
+        let encoding = self.tokenizer.encode(input, true).map_err(tokenizer_failed)?;
+        Ok(encoding.get_ids().to_vec())
     }
 
-    pub fn decode(&self, input: &[u32]) -> String {
-        self.tokenizer.decode(input.to_vec(), true).unwrap()
+    pub fn decode(&self, input: &[u32]) -> Result<String, TaskManagerError> {
+        self.tokenizer
+            .decode(input.to_vec(), true)
+            .map_err(tokenizer_failed)
     }
 }
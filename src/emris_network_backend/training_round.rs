@@ -0,0 +1,220 @@
+use crate::errors::TaskManagerError;
+use crate::training_task::TrainingConfig;
+use ic_cdk::export::candid::{CandidType};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum TrainingRoundStatus {
+    Collecting, // Tasks are out with workers; deltas are being submitted
+    Published,  // Deltas were aggregated into `target_version`, which is now promoted
+    Cancelled,
+}
+
+// How the participants' deltas are combined. The robust variants bound the influence a single
+// poisoned update has on the published weights, at the cost of ignoring sample counts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum Aggregator {
+    #[default]
+    FedAvg,                    // Mean weighted by sample count
+    Median,                    // Coordinate-wise median
+    TrimmedMean { trim: u32 }, // Coordinate-wise mean without the `trim` lowest and highest
+    Krum { byzantine: u32 },   // Delta closest to its neighbours, tolerating `byzantine` bad ones
+}
+
+// What an admin provides to start a round. Every entry of `training_data` becomes one task,
+// so each participant trains on its own shard of the data.
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct TrainingRoundRequest {
+    pub model_id: String,
+    pub target_version: String, // Version the aggregated weights are published as
+    pub training_data: Vec<Vec<u8>>,
+    pub config: TrainingConfig,
+    pub min_submissions: u32, // Deltas needed before the round can be aggregated
+    pub aggregator: Aggregator,
+    pub clip_norm: Option<f32>, // Deltas with a larger L2 norm are scaled down to it
+}
+
+// One round of federated training of a model, started from its promoted version.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub struct TrainingRound {
+    pub id: String,
+    pub model_id: String,
+    pub base_version: String, // Version the participants' deltas are relative to
+    pub target_version: String,
+    pub task_ids: Vec<String>,
+    pub min_submissions: u32,
+    pub status: TrainingRoundStatus,
+    pub aggregated_tasks: Vec<String>, // Tasks whose deltas went into the published weights
+    #[serde(default)]
+    pub aggregator: Aggregator,
+    #[serde(default)]
+    pub clip_norm: Option<f32>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+// Weights and deltas are exchanged as flat little-endian f32 tensors.
+pub fn decode_weights(bytes: &[u8]) -> Result<Vec<f32>, TaskManagerError> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(TaskManagerError::invalid_argument(
+            "model_weights",
+            "must be a sequence of little-endian f32 values",
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+        .collect())
+}
+
+pub fn encode_weights(weights: &[f32]) -> Vec<u8> {
+    weights.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+impl Aggregator {
+    // Check the aggregator can work with as few as `min_submissions` deltas.
+    pub fn validate(&self, min_submissions: u32) -> Result<(), TaskManagerError> {
+        match *self {
+            Aggregator::TrimmedMean { trim } if trim.saturating_mul(2) >= min_submissions => {
+                Err(TaskManagerError::invalid_argument(
+                    "aggregator",
+                    "trimmed mean must keep at least one delta of min_submissions",
+                ))
+            }
+            // Widened so the bound cannot overflow, nor saturate into a pass.
+            Aggregator::Krum { byzantine }
+                if u64::from(byzantine) * 2 + 3 > u64::from(min_submissions) =>
+            {
+                Err(TaskManagerError::invalid_argument(
+                    "aggregator",
+                    "Krum needs min_submissions of at least 2 * byzantine + 3",
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn l2_norm(values: &[f32]) -> f64 {
+    values.iter().map(|&value| (value as f64).powi(2)).sum::<f64>().sqrt()
+}
+
+fn squared_distance(a: &[f32], b: &[f32]) -> f64 {
+    a.iter().zip(b).map(|(&x, &y)| (x as f64 - y as f64).powi(2)).sum()
+}
+
+// Mean of the values left after sorting and dropping `trim` from each end.
+fn trimmed_mean(mut values: Vec<f32>, trim: usize) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let kept = &values[trim..values.len() - trim];
+    (kept.iter().map(|&value| value as f64).sum::<f64>() / kept.len() as f64) as f32
+}
+
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(f32::total_cmp);
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        ((values[middle - 1] as f64 + values[middle] as f64) / 2.0) as f32
+    } else {
+        values[middle]
+    }
+}
+
+// FedAvg: the mean of the deltas, each weighted by the number of samples it was trained on.
+fn federated_average(
+    deltas: &[(Vec<f32>, u64)],
+    size: usize,
+) -> Result<Vec<f32>, TaskManagerError> {
+    // Summed as u128 so no set of reported counts can overflow.
+    let total_samples: u128 = deltas.iter().map(|(_, samples)| *samples as u128).sum();
+    if total_samples == 0 {
+        return Err(TaskManagerError::invalid_argument(
+            "num_samples",
+            "at least one delta must be trained on a sample",
+        ));
+    }
+    let mut average = vec![0f64; size];
+    for (delta, samples) in deltas {
+        let share = *samples as f64 / total_samples as f64;
+        for (sum, value) in average.iter_mut().zip(delta) {
+            *sum += share * *value as f64;
+        }
+    }
+    Ok(average.into_iter().map(|value| value as f32).collect())
+}
+
+// Krum (Blanchard et al., 2017): pick the delta whose n - f - 2 nearest neighbours are closest,
+// which an attacker controlling at most f deltas cannot pull far from the honest ones.
+fn krum(deltas: &[(Vec<f32>, u64)], byzantine: usize) -> Vec<f32> {
+    let neighbours = deltas.len().saturating_sub(byzantine.saturating_add(2)).max(1);
+    let scores: Vec<f64> = deltas
+        .iter()
+        .enumerate()
+        .map(|(index, (delta, _))| {
+            let mut distances: Vec<f64> = deltas
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != index)
+                .map(|(_, (other, _))| squared_distance(delta, other))
+                .collect();
+            distances.sort_by(f64::total_cmp);
+            distances.iter().take(neighbours).sum()
+        })
+        .collect();
+    // Ties go to the earlier delta so the choice is deterministic.
+    let chosen = (0..deltas.len())
+        .min_by(|&a, &b| scores[a].total_cmp(&scores[b]).then(a.cmp(&b)))
+        .unwrap_or_default();
+    deltas[chosen].0.clone()
+}
+
+// Combine the participants' deltas with `aggregator`, after clipping each to `clip_norm`, and add
+// the result to `base`. Every delta must have the length of `base`.
+pub fn aggregate(
+    aggregator: Aggregator,
+    clip_norm: Option<f32>,
+    base: &[f32],
+    deltas: &[(Vec<f32>, u64)],
+) -> Result<Vec<f32>, TaskManagerError> {
+    if deltas.is_empty() {
+        return Err(TaskManagerError::invalid_argument("deltas", "cannot be empty"));
+    }
+    if deltas.iter().any(|(delta, _)| delta.len() != base.len()) {
+        return Err(TaskManagerError::invalid_argument(
+            "model_weights",
+            "delta does not match the size of the base weights",
+        ));
+    }
+    let mut deltas = deltas.to_vec();
+    if let Some(clip_norm) = clip_norm {
+        for (delta, _) in &mut deltas {
+            let norm = l2_norm(delta);
+            if norm > clip_norm as f64 {
+                let scale = clip_norm as f64 / norm;
+                delta.iter_mut().for_each(|value| *value = (*value as f64 * scale) as f32);
+            }
+        }
+    }
+
+    let coordinate = |index: usize| -> Vec<f32> {
+        deltas.iter().map(|(delta, _)| delta[index]).collect()
+    };
+    let combined = match aggregator {
+        Aggregator::FedAvg => federated_average(&deltas, base.len())?,
+        Aggregator::Median => (0..base.len()).map(|index| median(coordinate(index))).collect(),
+        Aggregator::TrimmedMean { trim } => {
+            let trim = trim as usize;
+            if trim.saturating_mul(2) >= deltas.len() {
+                return Err(TaskManagerError::invalid_argument(
+                    "aggregator",
+                    "trimmed mean would drop every delta",
+                ));
+            }
+            (0..base.len())
+                .map(|index| trimmed_mean(coordinate(index), trim))
+                .collect()
+        }
+        Aggregator::Krum { byzantine } => krum(&deltas, byzantine as usize),
+    };
+    Ok(base.iter().zip(combined).map(|(weight, delta)| weight + delta).collect())
+}
@@ -1,13 +1,229 @@
+use crate::errors::TaskManagerError;
 use ic_cdk::export::candid::{CandidType};
 use serde::{Deserialize, Serialize};
 
+// Upper bounds accepted by `TrainingConfig::validate`.
+pub const MAX_BATCH_SIZE: u32 = 1_024;
+pub const MAX_EPOCHS: u32 = 100;
+pub const MAX_SEQUENCE_LENGTH: u32 = 4_096;
+
+pub const MAX_TRAINING_TASK_PAGE_SIZE: u32 = 100;
+
+// Largest `num_samples` a worker may report with its results.
+pub const MAX_NUM_SAMPLES: u64 = 1_000_000_000_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum Optimizer {
+    #[default]
+    AdamW,
+    Adam,
+    Sgd,
+}
+
+// How the learning rate evolves after warmup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum LearningRateSchedule {
+    #[default]
+    Constant,
+    Linear, // Decays linearly to zero by the last step
+    Cosine, // Follows half a cosine down to zero by the last step
+}
+
+// Hyperparameters a worker trains with, handed out together with the task.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub struct TrainingConfig {
+    pub optimizer: Optimizer,
+    pub learning_rate: f64, // Peak learning rate, reached at the end of warmup
+    pub lr_schedule: LearningRateSchedule,
+    pub warmup_steps: u32, // Steps over which the learning rate rises linearly from zero
+    pub batch_size: u32,
+    pub epochs: u32,
+    pub max_sequence_length: u32, // Longer examples are truncated, in tokens
+    pub weight_decay: f64,
+    pub seed: u64, // Seeds shuffling and initialization so runs can be reproduced
+}
+
+// Matches the defaults of the `fine_tuning` tool.
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            optimizer: Optimizer::AdamW,
+            learning_rate: 2e-5,
+            lr_schedule: LearningRateSchedule::Constant,
+            warmup_steps: 0,
+            batch_size: 8,
+            epochs: 3,
+            max_sequence_length: 128,
+            weight_decay: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl TrainingConfig {
+    pub fn validate(&self) -> Result<(), TaskManagerError> {
+        if !(self.learning_rate > 0.0 && self.learning_rate <= 1.0) {
+            return Err(TaskManagerError::invalid_argument(
+                "learning_rate",
+                "must be greater than 0 and at most 1",
+            ));
+        }
+        if self.batch_size == 0 || self.batch_size > MAX_BATCH_SIZE {
+            return Err(TaskManagerError::invalid_argument(
+                "batch_size",
+                &format!("must be between 1 and {}", MAX_BATCH_SIZE),
+            ));
+        }
+        if self.epochs == 0 || self.epochs > MAX_EPOCHS {
+            return Err(TaskManagerError::invalid_argument(
+                "epochs",
+                &format!("must be between 1 and {}", MAX_EPOCHS),
+            ));
+        }
+        if self.max_sequence_length == 0 || self.max_sequence_length > MAX_SEQUENCE_LENGTH {
+            return Err(TaskManagerError::invalid_argument(
+                "max_sequence_length",
+                &format!("must be between 1 and {}", MAX_SEQUENCE_LENGTH),
+            ));
+        }
+        if !(self.weight_decay.is_finite() && (0.0..1.0).contains(&self.weight_decay)) {
+            return Err(TaskManagerError::invalid_argument(
+                "weight_decay",
+                "must be at least 0 and below 1",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Where a training task is in its lifecycle. See `can_transition_to` for the allowed moves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum TrainingTaskStatus {
+    #[default]
+    Pending,   // Waiting for a worker to claim it
+    Assigned,  // Claimed by `assignee`, which has not started yet
+    Running,   // The assignee reported that training started
+    Submitted, // Weights were submitted and await review
+    Validated, // An admin accepted the submitted weights
+    Failed,    // The assignee gave up or the submission was rejected; `error` says why
+    Cancelled, // Withdrawn by an admin
+}
+
+impl TrainingTaskStatus {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            TrainingTaskStatus::Validated
+                | TrainingTaskStatus::Failed
+                | TrainingTaskStatus::Cancelled
+        )
+    }
+
+    pub fn can_transition_to(self, next: TrainingTaskStatus) -> bool {
+        use TrainingTaskStatus::*;
+        match (self, next) {
+            (Pending, Assigned) | (Assigned, Running) => true,
+            (Assigned | Running, Submitted) => true,
+            (Submitted, Validated) => true,
+            (Assigned | Running | Submitted, Failed) => true,
+            (current, Cancelled) => !current.is_finished(),
+            _ => false,
+        }
+    }
+}
+
 // Define a struct representing a training task for a machine learning model.
-#[derive(Clone, Deserialize, Serialize, CandidType)]
+#[derive(Clone, Default, Deserialize, Serialize, CandidType)]
 pub struct TrainingTask {
     pub id: String,             // Unique identifier for the training task
     pub model_id: String,       // Identifier of the model associated with the training task
+    #[serde(with = "serde_bytes")]
     pub training_data: Vec<u8>, // Training data used for the task (binary format)
+    #[serde(with = "crate::stable_state::optional_blob")]
     pub model_weights: Option<Vec<u8>>, // Optional model weights (binary format)
-                                // TODO: Consider adding fields for training hyperparameters (e.g., learning rate, batch size).
                                 // TODO: Implement logic for training the model using the provided training data and model weights.
+    #[serde(default)]
+    pub config: TrainingConfig, // Hyperparameters the assignee trains with
+    #[serde(default)]
+    pub round_id: Option<String>, // Federated round the task belongs to; set by the canister
+    #[serde(default)]
+    pub num_samples: u64, // Examples the submitted weights were trained on, as reported
+    #[serde(default)]
+    pub results_hash: Vec<u8>, // SHA-256 of `model_weights`, computed when they are submitted
+    #[serde(default)]
+    pub status: TrainingTaskStatus, // Maintained by the canister
+    #[serde(default)]
+    pub assignee: Option<String>, // User that claimed the task
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub created_at: u64, // Canister time (ns); the timestamps are set by the canister
+    #[serde(default)]
+    pub assigned_at: Option<u64>,
+    #[serde(default)]
+    pub submitted_at: Option<u64>,
+    #[serde(default)]
+    pub finished_at: Option<u64>, // When the task was validated, failed or cancelled
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+// A training task without its training data and submitted weights, which can run to several
+// megabytes each; listings return these instead of whole tasks.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub struct TrainingTaskSummary {
+    pub id: String,
+    pub model_id: String,
+    pub config: TrainingConfig,
+    pub round_id: Option<String>,
+    pub num_samples: u64,
+    pub status: TrainingTaskStatus,
+    pub assignee: Option<String>,
+    pub error: Option<String>,
+    pub training_data_size: u64,   // Bytes of training data
+    pub results_size: Option<u64>, // Bytes of submitted weights, once submitted
+    pub results_hash: Vec<u8>,
+    pub created_at: u64,
+    pub assigned_at: Option<u64>,
+    pub submitted_at: Option<u64>,
+    pub finished_at: Option<u64>,
+    pub updated_at: u64,
+}
+
+impl From<&TrainingTask> for TrainingTaskSummary {
+    fn from(task: &TrainingTask) -> Self {
+        TrainingTaskSummary {
+            id: task.id.clone(),
+            model_id: task.model_id.clone(),
+            config: task.config.clone(),
+            round_id: task.round_id.clone(),
+            num_samples: task.num_samples,
+            status: task.status,
+            assignee: task.assignee.clone(),
+            error: task.error.clone(),
+            training_data_size: task.training_data.len() as u64,
+            results_size: task.model_weights.as_ref().map(|weights| weights.len() as u64),
+            results_hash: task.results_hash.clone(),
+            created_at: task.created_at,
+            assigned_at: task.assigned_at,
+            submitted_at: task.submitted_at,
+            finished_at: task.finished_at,
+            updated_at: task.updated_at,
+        }
+    }
+}
+
+// Page request for training task listings, oldest first. Cursors take the same "<key>:<id>"
+// form as model pages, keyed by creation time, so a cursor keeps its place as tasks are added.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, CandidType)]
+pub struct TrainingTaskQuery {
+    pub cursor: Option<String>, // `next_cursor` of the previous page; `None` for the first page
+    pub limit: u32,             // At most `MAX_TRAINING_TASK_PAGE_SIZE`
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, CandidType)]
+pub struct TrainingTaskPage {
+    pub tasks: Vec<TrainingTaskSummary>,
+    pub next_cursor: Option<String>, // `None` once the last page has been returned
+    pub total: u64,                  // Tasks matching the filter, across all pages
 }
@@ -0,0 +1,106 @@
+use crate::errors::TaskManagerError;
+use crate::training_task::TrainingTask;
+use ic_cdk::export::candid::{CandidType};
+use ic_cdk::export::Principal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Parts stay well below the 2 MiB ingress message limit, leaving room for the other arguments.
+pub const MAX_UPLOAD_PART_SIZE: u64 = 1_900_000;
+// Smaller parts only multiply update calls and map entries; uploads shorter than this are sent
+// as a single part.
+pub const MIN_UPLOAD_PART_SIZE: u64 = 64 << 10;
+// Parts are held on the heap until commit, next to the rest of the canister's state, so whole
+// uploads are kept to a fraction of the 4 GiB wasm32 address space.
+pub const MAX_UPLOAD_SIZE: u64 = 256 << 20;
+// Open uploads a single principal may hold at once.
+pub const MAX_OPEN_UPLOADS_PER_OWNER: usize = 4;
+// Declared bytes of all open uploads together, so parts held for commit cannot crowd out the
+// rest of the heap.
+pub const MAX_PENDING_UPLOAD_BYTES: u64 = 1 << 30;
+
+// Unfinished uploads are dropped once they have seen no part for this long.
+pub const UPLOAD_EXPIRY_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+// Where the bytes of an upload go once it is committed.
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub enum UploadTarget {
+    ModelWeights { model_id: String },
+    ModelVersionWeights { model_id: String, version: String },
+    TrainingData { task: Box<TrainingTask> }, // Created with the bytes as `training_data`
+    TrainingResults { task_id: String, num_samples: u64 },
+}
+
+// A multipart upload in progress. Parts can be sent in any order and sent again, so a client
+// that lost its connection resumes by uploading the parts `UploadStatus` does not list.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Upload {
+    pub id: String,
+    pub owner: Principal, // Only the principal that began the upload can add to or commit it
+    pub target: UploadTarget,
+    pub total_size: u64,
+    pub part_size: u64,        // Size of every part but the last, which holds the remainder
+    pub content_hash: Vec<u8>, // SHA-256 the assembled bytes must match
+    #[serde(with = "crate::stable_state::blob_map")]
+    pub parts: BTreeMap<u32, Vec<u8>>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+// What a client needs to resume an upload.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub struct UploadStatus {
+    pub id: String,
+    pub total_size: u64,
+    pub part_size: u64,
+    pub total_parts: u32,
+    pub received_parts: Vec<u32>,
+    pub expires_at: u64,
+}
+
+impl Upload {
+    pub fn total_parts(&self) -> u32 {
+        self.total_size.div_ceil(self.part_size) as u32
+    }
+
+    // Expected length of part `index`.
+    pub fn part_len(&self, index: u32) -> u64 {
+        let start = index as u64 * self.part_size;
+        self.part_size.min(self.total_size - start)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.updated_at.saturating_add(UPLOAD_EXPIRY_NS)
+    }
+
+    pub fn status(&self) -> UploadStatus {
+        UploadStatus {
+            id: self.id.clone(),
+            total_size: self.total_size,
+            part_size: self.part_size,
+            total_parts: self.total_parts(),
+            received_parts: self.parts.keys().copied().collect(),
+            expires_at: self.updated_at.saturating_add(UPLOAD_EXPIRY_NS),
+        }
+    }
+}
+
+pub fn validate_upload_layout(total_size: u64, part_size: u64) -> Result<(), TaskManagerError> {
+    if total_size == 0 || total_size > MAX_UPLOAD_SIZE {
+        return Err(TaskManagerError::invalid_argument(
+            "total_size",
+            &format!("must be between 1 and {}", MAX_UPLOAD_SIZE),
+        ));
+    }
+    let min_part_size = MIN_UPLOAD_PART_SIZE.min(total_size);
+    if part_size < min_part_size || part_size > MAX_UPLOAD_PART_SIZE {
+        return Err(TaskManagerError::invalid_argument(
+            "part_size",
+            &format!(
+                "must be between {} and {}",
+                min_part_size, MAX_UPLOAD_PART_SIZE
+            ),
+        ));
+    }
+    Ok(())
+}
@@ -1,4 +1,6 @@
+use crate::rate_limit::RateLimitTier;
 use ic_cdk::export::candid::{CandidType};
+use ic_cdk::export::Principal;
 use serde::{Deserialize, Serialize};
 
 // Define a struct representing a user in the system.
@@ -9,6 +11,17 @@ pub struct User {
     pub resources: u64, // Number of resources owned by the user
     pub rewards: u64,   // Number of rewards earned by the user
     pub rate_limit_tokens: u64, // Number of rate limit tokens available to the user
+    pub owner: Option<Principal>, // Principal that registered the user; set by the canister
+    #[serde(default)]
+    pub tier: RateLimitTier, // Rate limit tier; only admins can raise it
+    #[serde(default)]
+    pub rate_limit_refilled_at: u64, // Canister time (ns) `rate_limit_tokens` was last topped up
                         // TODO: Consider adding additional fields, such as user's display name or email address.
-                        // TODO: Implement rate limiting logic based on the `rate_limit_tokens` field.
+}
+
+// Resources a user has committed to serving one model.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub struct ResourcePledge {
+    pub model_id: String,
+    pub amount: u64, // Part of the user's `resources`; a user never pledges more than they own
 }
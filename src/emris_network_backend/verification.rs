@@ -0,0 +1,209 @@
+use crate::errors::TaskManagerError;
+use ic_cdk::export::candid::{CandidType};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Controls how many independent users compute each shard and how their results are compared.
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct VerificationConfig {
+    pub redundancy: u32, // Number of distinct users each shard is assigned to
+    pub tolerance: f32,  // Max absolute difference between results read as f32; 0 compares hashes
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            redundancy: 3,
+            tolerance: 0.0,
+        }
+    }
+}
+
+impl VerificationConfig {
+    pub fn validate(&self) -> Result<(), TaskManagerError> {
+        if self.redundancy == 0 {
+            return Err(TaskManagerError::invalid_argument(
+                "redundancy",
+                "must be at least 1",
+            ));
+        }
+        if !self.tolerance.is_finite() || self.tolerance < 0.0 {
+            return Err(TaskManagerError::invalid_argument(
+                "tolerance",
+                "must be a finite, non-negative number",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, CandidType)]
+pub enum VerificationStatus {
+    Pending,  // Waiting for enough matching results
+    Accepted, // A majority agreed; `accepted_results` holds the answer
+    Disputed, // Every replica reported and no majority emerged
+}
+
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct Submission {
+    pub user_id: String,
+    pub results: Vec<u32>,
+    pub result_hash: Vec<u8>,
+    pub accepted: Option<bool>, // None until a majority answer is accepted
+}
+
+// Results collected for one shard (every replica of the same chunk index).
+#[derive(Clone, Deserialize, Serialize, CandidType)]
+pub struct ShardVerification {
+    pub shard_id: String,
+    pub model_id: String,
+    pub redundancy: u32, // Copied from the config at distribution time
+    pub tolerance: f32,
+    pub submissions: Vec<Submission>,
+    pub status: VerificationStatus,
+    pub accepted_results: Option<Vec<u32>>,
+}
+
+// Users whose submissions were decided by a single `ShardVerification::record` call.
+#[derive(Default)]
+pub struct VerificationOutcome {
+    pub accepted: Vec<String>, // Matched the accepted answer
+    pub rejected: Vec<String>, // Proven wrong by the accepted answer
+}
+
+// SHA-256 over the little-endian encoding of a result vector.
+pub fn result_hash(results: &[u32]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for value in results {
+        hasher.update(value.to_le_bytes());
+    }
+    hasher.finalize().to_vec()
+}
+
+// With a zero tolerance results must be bit-identical; otherwise each element is read as an
+// f32 and compared within `tolerance`, which absorbs GPU floating-point drift between workers.
+pub fn results_match(a: &[u32], b: &[u32], tolerance: f32) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    if tolerance == 0.0 {
+        return a == b;
+    }
+    a.iter().zip(b).all(|(&x, &y)| {
+        x == y || (f32::from_bits(x) - f32::from_bits(y)).abs() <= tolerance
+    })
+}
+
+impl ShardVerification {
+    pub fn new(shard_id: String, model_id: String, config: &VerificationConfig) -> Self {
+        Self {
+            shard_id,
+            model_id,
+            redundancy: config.redundancy,
+            tolerance: config.tolerance,
+            submissions: Vec::new(),
+            status: VerificationStatus::Pending,
+            accepted_results: None,
+        }
+    }
+
+    // The record without any submitted results while the shard is pending, so a holder cannot
+    // copy another worker's answer; hashes and submitters stay visible.
+    pub fn redacted(mut self) -> Self {
+        if self.status == VerificationStatus::Pending {
+            for submission in &mut self.submissions {
+                submission.results.clear();
+            }
+        }
+        self
+    }
+
+    fn majority(&self) -> usize {
+        self.redundancy as usize / 2 + 1
+    }
+
+    fn matches(&self, a: &Submission, b: &[u32]) -> bool {
+        if self.tolerance == 0.0 {
+            a.result_hash == result_hash(b)
+        } else {
+            results_match(&a.results, b, self.tolerance)
+        }
+    }
+
+    // Record a submission and decide the shard if possible, reporting every submission whose
+    // verdict was settled by this call.
+    pub fn record(
+        &mut self,
+        user_id: &str,
+        results: Vec<u32>,
+    ) -> Result<VerificationOutcome, TaskManagerError> {
+        if self.submissions.iter().any(|s| s.user_id == user_id) {
+            return Err(TaskManagerError::DuplicateSubmission {
+                shard_id: self.shard_id.clone(),
+                user_id: user_id.to_string(),
+            });
+        }
+        self.submissions.push(Submission {
+            user_id: user_id.to_string(),
+            result_hash: result_hash(&results),
+            results,
+            accepted: None,
+        });
+
+        match self.status {
+            VerificationStatus::Accepted => {
+                let accepted = self.accepted_results.clone().unwrap_or_default();
+                let matched = self.matches(self.submissions.last().unwrap(), &accepted);
+                self.submissions.last_mut().unwrap().accepted = Some(matched);
+                let mut outcome = VerificationOutcome::default();
+                if matched {
+                    outcome.accepted.push(user_id.to_string());
+                } else {
+                    outcome.rejected.push(user_id.to_string());
+                }
+                Ok(outcome)
+            }
+            VerificationStatus::Disputed => Ok(VerificationOutcome::default()),
+            VerificationStatus::Pending => Ok(self.try_decide()),
+        }
+    }
+
+    fn try_decide(&mut self) -> VerificationOutcome {
+        let winner = self.submissions.iter().find(|candidate| {
+            let votes = self
+                .submissions
+                .iter()
+                .filter(|other| self.matches(other, &candidate.results))
+                .count();
+            votes >= self.majority()
+        });
+        let accepted = match winner {
+            Some(winner) => winner.results.clone(),
+            None => {
+                // Nobody is proven wrong without a majority, so verdicts stay undecided.
+                if self.submissions.len() >= self.redundancy as usize {
+                    self.status = VerificationStatus::Disputed;
+                }
+                return VerificationOutcome::default();
+            }
+        };
+
+        let mut outcome = VerificationOutcome::default();
+        let verdicts: Vec<bool> = self
+            .submissions
+            .iter()
+            .map(|submission| self.matches(submission, &accepted))
+            .collect();
+        for (submission, matched) in self.submissions.iter_mut().zip(verdicts) {
+            submission.accepted = Some(matched);
+            if matched {
+                outcome.accepted.push(submission.user_id.clone());
+            } else {
+                outcome.rejected.push(submission.user_id.clone());
+            }
+        }
+        self.status = VerificationStatus::Accepted;
+        self.accepted_results = Some(accepted);
+        outcome
+    }
+}